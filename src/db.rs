@@ -1,30 +1,226 @@
 //use crate::sqlite::SQLite;
 //use crate::rocksdb::RocksDB;
 use crate::postgres::Postgres;
+use crate::properties::Properties;
 
 use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::Arc;
-use std::cell::UnsafeCell;
-use tokio::sync::{Mutex, RwLock};
 
 use async_trait::async_trait;
 
 pub type DBType = Postgres;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IsolationLevel {
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+}
+
+impl std::str::FromStr for IsolationLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().replace(['_', '-'], " ").as_str() {
+            "read committed" => Ok(IsolationLevel::ReadCommitted),
+            "repeatable read" => Ok(IsolationLevel::RepeatableRead),
+            "serializable" => Ok(IsolationLevel::Serializable),
+            _ => Err(anyhow!("unknown isolation level {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for IsolationLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IsolationLevel::ReadCommitted => write!(f, "READ COMMITTED"),
+            IsolationLevel::RepeatableRead => write!(f, "REPEATABLE READ"),
+            IsolationLevel::Serializable => write!(f, "SERIALIZABLE"),
+        }
+    }
+}
+
 #[async_trait]
 pub trait DB: Send + Sync {
-    async fn init(&mut self) -> Result<()>;
-    async fn insert(&mut self, table: &str, key: &str, values: &HashMap<&str, String>) -> Result<()>;
-    async fn update(&mut self, table: &str, key: &str, values: &HashMap<&str, String>) -> Result<()>;
-    async fn read(&mut self, table: &str, key: &str, result: &mut HashMap<String, String>) -> Result<()>;
+    async fn init(&mut self) -> DbResult<()> {
+        Ok(())
+    }
+
+    /// Creates `table` unless it already exists.
+    async fn create_table(&mut self, table: &str) -> DbResult<()>;
+    /// Creates a secondary index on `field` of `table` unless it already
+    /// exists.
+    async fn create_index(&mut self, table: &str, field: &str) -> DbResult<()>;
+    /// Drops `table` if it exists.
+    async fn drop_table(&mut self, table: &str) -> DbResult<()>;
+    /// Deletes every record in `table`.
+    async fn truncate_table(&mut self, table: &str) -> DbResult<()>;
+    /// The number of records in `table`.
+    async fn count(&mut self, table: &str) -> DbResult<u64>;
+
+    async fn insert(&mut self, table: &str, key: &str, values: &HashMap<&str, String>) -> DbResult<()>;
+    async fn update(&mut self, table: &str, key: &str, values: &HashMap<&str, String>) -> DbResult<()>;
+    async fn read(&mut self, table: &str, key: &str, result: &mut HashMap<String, String>) -> DbResult<()>;
+    /// Reads up to `count` records in key order, starting at `start_key`.
+    async fn scan(
+        &mut self,
+        table: &str,
+        start_key: &str,
+        count: usize,
+        result: &mut Vec<HashMap<String, String>>,
+    ) -> DbResult<()>;
+    /// Reads the first record, in `field` order, whose `field` is at least
+    /// `value`. Meant to go through a secondary index on `field`.
+    async fn read_by_field(
+        &mut self,
+        table: &str,
+        field: &str,
+        value: &str,
+        result: &mut HashMap<String, String>,
+    ) -> DbResult<()>;
+
+    /// Inserts several records at once. Backends without a bulk path fall
+    /// back to one `insert` per record.
+    async fn batch_insert(&mut self, table: &str, records: &[(String, HashMap<&str, String>)]) -> DbResult<()> {
+        for (key, values) in records {
+            self.insert(table, key, values).await?;
+        }
+        Ok(())
+    }
+
+    /// Starts a transaction that every following call on this handle runs
+    /// in, until `commit` or `rollback`. `None` uses the backend's default
+    /// isolation level. Backends without transactions run each call on its
+    /// own and treat these as no-ops.
+    async fn begin(&mut self, _isolation: Option<IsolationLevel>) -> DbResult<()> {
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> DbResult<()> {
+        Ok(())
+    }
+
+    async fn rollback(&mut self) -> DbResult<()> {
+        Ok(())
+    }
+}
+
+/// What kind of failure a `DbError` is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    /// The call, or waiting for a connection, took too long.
+    Timeout,
+    /// The connection failed or couldn't be made.
+    Connection,
+    /// The call clashed with another one: unique violations, serialization
+    /// failures, deadlocks and lock timeouts.
+    Conflict,
+    /// The record the call was about doesn't exist.
+    NotFound,
+    Other,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::Timeout => write!(f, "timeout"),
+            ErrorKind::Connection => write!(f, "connection"),
+            ErrorKind::Conflict => write!(f, "conflict"),
+            ErrorKind::NotFound => write!(f, "not-found"),
+            ErrorKind::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// An error from a `DB` call, classified by the backend.
+#[derive(Debug)]
+pub struct DbError {
+    kind: ErrorKind,
+    retryable: bool,
+    serialization_failure: bool,
+    source: anyhow::Error,
+}
+
+pub type DbResult<T> = std::result::Result<T, DbError>;
+
+impl DbError {
+    /// An error of `kind`, worth retrying if the kind is a transient one.
+    pub fn new(kind: ErrorKind, source: anyhow::Error) -> Self {
+        DbError {
+            kind,
+            retryable: matches!(kind, ErrorKind::Timeout | ErrorKind::Connection | ErrorKind::Conflict),
+            serialization_failure: false,
+            source,
+        }
+    }
+
+    /// Overrides whether the error may go away if the same call is made
+    /// again.
+    pub fn with_retryable(mut self, retryable: bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    pub fn with_serialization_failure(mut self, serialization_failure: bool) -> Self {
+        self.serialization_failure = serialization_failure;
+        self
+    }
+
+    pub fn timeout() -> Self {
+        DbError::new(ErrorKind::Timeout, anyhow!("timed out"))
+    }
+
+    pub fn not_found(key: &str) -> Self {
+        DbError::new(ErrorKind::NotFound, anyhow!("{} not found", key))
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The error the backend reported.
+    pub fn cause(&self) -> &anyhow::Error {
+        &self.source
+    }
+
+    /// Whether the transaction was aborted because it couldn't be serialized
+    /// with concurrent ones, and is worth retrying as a whole.
+    pub fn is_serialization_failure(&self) -> bool {
+        self.serialization_failure
+    }
+
+    /// Whether the error may go away if the same call is made again.
+    pub fn is_retryable(&self) -> bool {
+        self.retryable
+    }
+}
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl std::error::Error for DbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.source()
+    }
+}
+
+/// Errors the backend didn't classify.
+impl From<anyhow::Error> for DbError {
+    fn from(err: anyhow::Error) -> Self {
+        DbError::new(ErrorKind::Other, err)
+    }
 }
 
-pub async fn create_db(db: &str) -> Result<DBType> {
+pub async fn create_db(db: &str, props: &Properties) -> Result<DBType> {
     match db {
         //"sqlite" => Ok(Rc::new(RefCell::new(SQLite::new()?))),
         //"rocksdb" => Ok(Rc::new(RocksDB::new()?)),
-        "postgres" => Ok(Postgres::new().await?),
+        "postgres" => Ok(Postgres::new(&props.postgres).await?),
         db => Err(anyhow!("{} is an invalid database name", db)),
     }
 }
@@ -0,0 +1,220 @@
+//! Runs `load` and `run` across several `ycsb worker` processes driven by one
+//! `ycsb coordinator`. The coordinator does any table setup itself, hands
+//! every worker its share of the phase, starts them together and merges the
+//! stats they send back into one report.
+//!
+//! Messages are JSON, one per line.
+
+use crate::properties::Properties;
+use crate::stats::Summary;
+use crate::{db, derive_seed, partition, phase_count, prepare_phase, report, run_command, Opt, Phase};
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    /// Coordinator to worker: set up `count` operations of `phase`. For
+    /// `load`, the worker inserts the keys from `insert_start` on; for `run`,
+    /// worker `worker` of `workers` inserts every `workers`-th key past
+    /// `recordcount`. `seed` is the worker's share of the workload's seed, if
+    /// it has one.
+    Prepare { phase: String, insert_start: u64, count: usize, seed: Option<u64>, worker: usize, workers: usize },
+    /// Worker to coordinator: the phase is set up.
+    Ready,
+    /// Coordinator to worker: start the prepared phase.
+    Start,
+    /// Worker to coordinator: the phase finished.
+    Done { threads: usize, summary: Box<Summary> },
+    /// Coordinator to worker: there are no more phases.
+    Shutdown,
+}
+
+struct Connection {
+    reader: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Self {
+        let (reader, writer) = stream.into_split();
+        Connection {
+            reader: BufReader::new(reader).lines(),
+            writer,
+        }
+    }
+
+    async fn send(&mut self, msg: &Message) -> Result<()> {
+        let mut line = serde_json::to_string(msg)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Result<Message> {
+        match self.reader.next_line().await? {
+            Some(line) => Ok(serde_json::from_str(&line)?),
+            None => Err(anyhow!("connection closed")),
+        }
+    }
+}
+
+pub async fn coordinator(opt: &Opt, props: &Properties, db: &mut db::DBType, commands: &[String]) -> Result<()> {
+    if props.workload != "core" {
+        bail!("only the core workload can be distributed");
+    }
+    let listener = TcpListener::bind(&opt.address).await?;
+    println!("Waiting for {} workers on {}", opt.workers, opt.address);
+    let mut workers = vec![];
+    while workers.len() < opt.workers {
+        let (stream, addr) = listener.accept().await?;
+        println!("Worker {} connected from {}", workers.len(), addr);
+        workers.push(Connection::new(stream));
+    }
+
+    for cmd in commands {
+        if cmd != "load" && cmd != "run" {
+            run_command(cmd, opt, props, db).await?;
+            continue;
+        }
+        prepare_phase(cmd, opt, props, db).await?;
+        let expected = phase_count(cmd, props);
+        let (runtime, threads, merged) = distribute(&mut workers, cmd, expected, props).await?;
+        report(cmd, threads, runtime, expected, &merged)?;
+    }
+
+    for worker in workers.iter_mut() {
+        worker.send(&Message::Shutdown).await?;
+    }
+    Ok(())
+}
+
+/// Has `workers` perform `count` operations of `cmd` between them, and
+/// returns how long they took, their total threads and their merged stats.
+async fn distribute(workers: &mut [Connection], cmd: &str, count: usize, props: &Properties) -> Result<(u128, usize, Summary)> {
+    let total = workers.len();
+    let mut insert_start = props.insert_start;
+    for (idx, worker) in workers.iter_mut().enumerate() {
+        let share = partition(count, total, idx);
+        let phase = cmd.to_string();
+        let seed = props.seed.map(|seed| derive_seed(seed, idx));
+        worker.send(&Message::Prepare { phase, insert_start, count: share, seed, worker: idx, workers: total }).await?;
+        insert_start += share as u64;
+    }
+    for worker in workers.iter_mut() {
+        match worker.recv().await? {
+            Message::Ready => {}
+            _ => bail!("unexpected message from worker"),
+        }
+    }
+
+    let start = Instant::now();
+    for worker in workers.iter_mut() {
+        worker.send(&Message::Start).await?;
+    }
+    let mut total_threads = 0;
+    let mut merged = Summary::default();
+    for worker in workers.iter_mut() {
+        match worker.recv().await? {
+            Message::Done { threads, summary } => {
+                total_threads += threads;
+                merged.merge(&summary);
+            }
+            _ => bail!("unexpected message from worker"),
+        }
+    }
+    Ok((start.elapsed().as_millis(), total_threads, merged))
+}
+
+pub async fn worker(opt: &Opt, props: &Properties, db: &db::DBType) -> Result<()> {
+    let mut conn = Connection::new(TcpStream::connect(&opt.address).await?);
+    loop {
+        let (cmd, insert_start, count, seed, worker, workers) = match conn.recv().await? {
+            Message::Prepare { phase, insert_start, count, seed, worker, workers } => {
+                (phase, insert_start, count, seed, worker, workers)
+            }
+            Message::Shutdown => return Ok(()),
+            _ => bail!("unexpected message from coordinator"),
+        };
+        let mut props = props.clone();
+        props.insert_start = insert_start;
+        props.seed = seed;
+        props.run_insert_offset = worker as u64;
+        props.run_insert_stride = workers as u64;
+        let phase = Phase::new(&cmd, opt, &props)?;
+        conn.send(&Message::Ready).await?;
+        match conn.recv().await? {
+            Message::Start => {}
+            _ => bail!("unexpected message from coordinator"),
+        }
+
+        let summary = phase.execute(&cmd, opt, &props, db, count).await;
+        conn.send(&Message::Done { threads: opt.threads, summary: Box::new(summary) }).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use crate::trace::{self, TracedOperation};
+    use std::collections::HashSet;
+
+    /// Runs a phase on two workers over loopback, against a port nothing
+    /// listens on so that it needs no server.
+    #[tokio::test]
+    async fn test_run_on_two_workers() {
+        let props = testing::unreachable_props(
+            "operationcount = 200\nrecordcount = 10\nreadproportion = 0.5\ninsertproportion = 0.5",
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let dir = std::env::temp_dir();
+        let traces: Vec<_> = (0..2)
+            .map(|idx| dir.join(format!("ycsb-distributed-{}-{}.jsonl", std::process::id(), idx)))
+            .collect();
+        let worker = |trace: &std::path::Path| {
+            let opt = testing::opt(&["-t", "2", "--address", &address, "--record", trace.to_str().unwrap()]);
+            let props = props.clone();
+            async move {
+                let db = db::DBType::new(&props.postgres).await.unwrap();
+                worker(&opt, &props, &db).await
+            }
+        };
+        let coordinator = async {
+            let mut workers = vec![];
+            for _ in 0..2 {
+                workers.push(Connection::new(listener.accept().await.unwrap().0));
+            }
+            let result = distribute(&mut workers, "run", 200, &props).await;
+            for worker in workers.iter_mut() {
+                worker.send(&Message::Shutdown).await.unwrap();
+            }
+            result
+        };
+        let (result, first, second) = tokio::join!(coordinator, worker(&traces[0]), worker(&traces[1]));
+        first.unwrap();
+        second.unwrap();
+
+        let (_, threads, merged) = result.unwrap();
+        assert_eq!(threads, 4);
+        assert_eq!((merged.operations, merged.failures), (200, 200));
+        let mut inserted = HashSet::new();
+        let mut inserts = 0;
+        for path in &traces {
+            for record in trace::read(path.to_str().unwrap()).unwrap() {
+                if let TracedOperation::Insert { key, .. } = record.op {
+                    inserted.insert(key);
+                    inserts += 1;
+                }
+            }
+            std::fs::remove_file(path).unwrap();
+        }
+        assert!(inserts > 0);
+        assert_eq!(inserted.len(), inserts);
+    }
+}
@@ -2,15 +2,19 @@ mod acknowledged_counter_generator;
 mod constant_generator;
 mod counter_generator;
 mod discrete_generator;
+mod histogram_generator;
 mod uniform_long_generator;
+mod value_generator;
 mod zipfian_generator;
 
 pub use acknowledged_counter_generator::AcknowledgedCounterGenerator;
 pub use constant_generator::ConstantGenerator;
 pub use counter_generator::CounterGenerator;
 pub use discrete_generator::{DiscreteGenerator, WeightPair};
+pub use histogram_generator::HistogramGenerator;
 use rand::prelude::SmallRng;
 pub use uniform_long_generator::UniformLongGenerator;
+pub use value_generator::ValueGenerator;
 pub use zipfian_generator::ZipfianGenerator;
 
 use std::string::ToString;
@@ -0,0 +1,109 @@
+use super::{Generator, NumberGenerator};
+use anyhow::{anyhow, bail, Result};
+use rand::prelude::*;
+use std::io::BufRead;
+
+/// Draws values from a histogram in upstream YCSB's format: a
+/// `BlockSize\t<size>` line, then one `<bucket>\t<count>` line per bucket.
+/// Bucket `i` stands for values of `(i + 1) * size`, as upstream has it.
+pub struct HistogramGenerator {
+    block_size: u64,
+    /// Running totals of the bucket counts.
+    cumulative: Vec<u64>,
+    weighted_area: u64,
+}
+
+impl HistogramGenerator {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let file = std::fs::File::open(path).map_err(|err| anyhow!("{}: {}", path, err))?;
+        Self::from_reader(std::io::BufReader::new(file))
+    }
+
+    pub fn from_reader(reader: impl BufRead) -> Result<Self> {
+        let mut lines = reader.lines();
+        let header = lines.next().ok_or_else(|| anyhow!("empty histogram"))??;
+        let block_size = match header.split('\t').collect::<Vec<_>>()[..] {
+            ["BlockSize", size] => size.trim().parse()?,
+            _ => bail!("first line of histogram is not the BlockSize"),
+        };
+        let mut buckets = vec![];
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (bucket, count) = match line.split('\t').collect::<Vec<_>>()[..] {
+                [bucket, count] => (bucket.trim().parse::<usize>()?, count.trim().parse::<u64>()?),
+                _ => bail!("expected <bucket>\\t<count>, got {:?}", line),
+            };
+            if buckets.len() <= bucket {
+                buckets.resize(bucket + 1, 0);
+            }
+            buckets[bucket] = count;
+        }
+        Self::new(block_size, &buckets)
+    }
+
+    pub fn new(block_size: u64, buckets: &[u64]) -> Result<Self> {
+        let mut area = 0;
+        let mut weighted_area = 0;
+        let cumulative = buckets
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                area += count;
+                weighted_area += (i as u64 + 1) * count;
+                area
+            })
+            .collect();
+        if area == 0 {
+            bail!("histogram has no values");
+        }
+        Ok(Self {
+            block_size,
+            cumulative,
+            weighted_area,
+        })
+    }
+
+    fn area(&self) -> u64 {
+        *self.cumulative.last().unwrap()
+    }
+}
+
+impl Generator<u64> for HistogramGenerator {
+    fn next_value(&self, rng: &mut SmallRng) -> u64 {
+        let number = rng.gen_range(0..self.area());
+        let bucket = self.cumulative.partition_point(|&total| total <= number);
+        (bucket as u64 + 1) * self.block_size
+    }
+}
+
+impl NumberGenerator<u64> for HistogramGenerator {
+    fn mean(&self) -> u64 {
+        self.weighted_area * self.block_size / self.area()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_file() {
+        let file = "BlockSize\t10\n1\t3\n3\t1\n";
+        let histogram = HistogramGenerator::from_reader(file.as_bytes()).unwrap();
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut counts = std::collections::HashMap::new();
+        for _ in 0..10000 {
+            *counts.entry(histogram.next_value(&mut rng)).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 2);
+        assert!((7000..8000).contains(&counts[&20]));
+        assert!(counts.contains_key(&40));
+        assert_eq!(histogram.mean(), 25);
+
+        assert!(HistogramGenerator::from_reader("1\t3\n".as_bytes()).is_err());
+        assert!(HistogramGenerator::from_reader("BlockSize\t10\n".as_bytes()).is_err());
+    }
+}
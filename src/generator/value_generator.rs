@@ -0,0 +1,171 @@
+use anyhow::{anyhow, bail, Result};
+use rand::distributions::{Alphanumeric, DistString};
+use rand::prelude::*;
+
+/// Words `text` and `json` values are made of.
+const WORDS: &[&str] = &[
+    "the", "of", "and", "to", "in", "is", "for", "that", "on", "with", "as", "by", "at", "from", "this",
+    "be", "are", "or", "an", "it", "not", "was", "which", "have", "but", "all", "new", "more", "one",
+    "user", "order", "account", "status", "created", "updated", "name", "email", "address", "city",
+    "country", "price", "total", "item", "product", "service", "request", "response", "error", "value",
+    "time", "date", "id", "type", "data", "record", "session", "payment", "shipping", "customer",
+    "active", "pending", "complete", "default", "region",
+];
+
+/// What field values look like, which matters to storage engines that
+/// compress.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueGenerator {
+    /// Random letters and digits, as upstream YCSB does.
+    Alphanumeric,
+    /// Random printable ASCII. Text columns can't hold arbitrary bytes, so
+    /// this is as close to random as values get: about 6.6 bits per byte,
+    /// which deflate compresses by about 1.2.
+    Random,
+    /// A `random` run repeated to fill the value, just long enough that
+    /// values compress by about `ratio` with deflate. Ratios below that of
+    /// `random` give `random` values.
+    Compressible { ratio: f64 },
+    /// Words from a small dictionary separated by spaces.
+    Text,
+    /// JSON objects of numbers, booleans and words.
+    Json,
+}
+
+impl ValueGenerator {
+    /// `kind` is `alphanumeric`, `random`, `compressible`, `text` or `json`;
+    /// `ratio` is only used by `compressible`.
+    pub fn new(kind: &str, ratio: f64) -> Result<Self> {
+        match kind.to_lowercase().as_str() {
+            "alphanumeric" => Ok(ValueGenerator::Alphanumeric),
+            "random" => Ok(ValueGenerator::Random),
+            "compressible" if ratio >= 1.0 => Ok(ValueGenerator::Compressible { ratio }),
+            "compressible" => bail!("valuecompressionratio must be at least 1"),
+            "text" => Ok(ValueGenerator::Text),
+            "json" => Ok(ValueGenerator::Json),
+            _ => Err(anyhow!("unknown value generator {}", kind)),
+        }
+    }
+
+    /// A value exactly `len` bytes long.
+    pub fn next_value(&self, rng: &mut SmallRng, len: usize) -> String {
+        match self {
+            ValueGenerator::Alphanumeric => Alphanumeric.sample_string(rng, len),
+            ValueGenerator::Random => printable(rng, len),
+            ValueGenerator::Compressible { ratio } => {
+                // The run compresses to about `bits / 8` of its length, and
+                // its repeats to next to nothing.
+                let bits = PRINTABLE_CHARS.log2();
+                let unique = ((len as f64 * 8.0 / bits / ratio).ceil() as usize).clamp(len.min(1), len);
+                let run = printable(rng, unique);
+                run.chars().cycle().take(len).collect()
+            }
+            ValueGenerator::Text => {
+                let mut text = String::with_capacity(len + 16);
+                while text.len() < len {
+                    if !text.is_empty() {
+                        text.push(' ');
+                    }
+                    text.push_str(WORDS.choose(rng).unwrap());
+                }
+                text.truncate(len);
+                text
+            }
+            ValueGenerator::Json => json(rng, len),
+        }
+    }
+}
+
+/// Number of printable ASCII characters, `' '` to `'~'`.
+const PRINTABLE_CHARS: f64 = 95.0;
+
+fn printable(rng: &mut SmallRng, len: usize) -> String {
+    (0..len).map(|_| rng.gen_range(b' '..=b'~') as char).collect()
+}
+
+/// A JSON object of random entries, padded with a last `"pad"` entry to be
+/// exactly `len` bytes. Lengths too short for an object get a JSON string,
+/// or digits.
+fn json(rng: &mut SmallRng, len: usize) -> String {
+    // `,"pad":""}` around the padding.
+    const PAD: usize = 10;
+    let mut doc = String::from("{");
+    for idx in 0.. {
+        let key = WORDS.choose(rng).unwrap();
+        let entry = match rng.gen_range(0..3) {
+            0 => format!("\"{}{}\":{}", key, idx, rng.gen_range(0..1_000_000)),
+            1 => format!("\"{}{}\":{}", key, idx, rng.gen::<bool>()),
+            _ => format!("\"{}{}\":\"{}\"", key, idx, WORDS.choose(rng).unwrap()),
+        };
+        let comma = usize::from(idx > 0);
+        if doc.len() + comma + entry.len() + PAD > len {
+            break;
+        }
+        if comma > 0 {
+            doc.push(',');
+        }
+        doc.push_str(&entry);
+    }
+    let separator = if doc.len() > 1 { "," } else { "" };
+    let fill = len.checked_sub(doc.len() + separator.len() + PAD - 1);
+    match fill {
+        Some(fill) => {
+            doc.push_str(separator);
+            doc.push_str("\"pad\":\"");
+            doc.push_str(&Alphanumeric.sample_string(rng, fill));
+            doc.push_str("\"}");
+            doc
+        }
+        None if len >= 2 => format!("\"{}\"", Alphanumeric.sample_string(rng, len - 2)),
+        None => (0..len).map(|_| rng.gen_range(b'1'..=b'9') as char).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lengths_and_shapes() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let generators = ["alphanumeric", "random", "compressible", "text", "json"]
+            .map(|kind| ValueGenerator::new(kind, 4.0).unwrap());
+        for len in [0, 1, 2, 5, 11, 100, 1000] {
+            for generator in &generators {
+                assert_eq!(generator.next_value(&mut rng, len).len(), len, "{:?}", generator);
+            }
+            if len > 0 {
+                let value = ValueGenerator::Json.next_value(&mut rng, len);
+                serde_json::from_str::<serde_json::Value>(&value).unwrap();
+            }
+        }
+
+        assert!(ValueGenerator::new("compressible", 0.5).is_err());
+        assert!(ValueGenerator::new("words", 1.0).is_err());
+    }
+
+    /// How much deflate compresses a run of values, the way a storage engine
+    /// compresses a page of records.
+    fn compression_ratio(generator: ValueGenerator, len: usize) -> f64 {
+        use flate2::{write::DeflateEncoder, Compression};
+        use std::io::Write;
+
+        let mut rng = SmallRng::seed_from_u64(1);
+        let page: String = (0..1000).map(|_| generator.next_value(&mut rng, len)).collect();
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(page.as_bytes()).unwrap();
+        page.len() as f64 / encoder.finish().unwrap().len() as f64
+    }
+
+    #[test]
+    fn test_compression_ratio() {
+        for len in [100, 1000] {
+            for target in [1.5, 2.0, 4.0] {
+                let ratio = compression_ratio(ValueGenerator::Compressible { ratio: target }, len);
+                assert!((ratio / target - 1.0).abs() < 0.15, "{} bytes: {} for {}", len, ratio, target);
+            }
+            let ratio = compression_ratio(ValueGenerator::Random, len);
+            assert!((1.1..1.3).contains(&ratio), "{} bytes: {}", len, ratio);
+        }
+    }
+}
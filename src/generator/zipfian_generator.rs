@@ -1,15 +1,18 @@
 use super::{Generator, NumberGenerator};
 use rand::prelude::*;
+use arc_swap::ArcSwap;
+use std::sync::{Arc, Mutex};
 
 pub const ZIPFIAN_CONSTANT: f64 = 0.99;
 
-#[allow(dead_code)]
+/// The parameters that depend on the item count, along with the count they
+/// were computed for. They're swapped in together, so that a reader never
+/// pairs one count's `zetan` with another's `eta`, and without a lock.
+#[derive(Clone, Copy)]
 struct ZipfianParameters {
-    alpha: f64,
+    items: u64,
     zetan: f64,
     eta: f64,
-    theta: f64,
-    zeta2theta: f64,
 }
 
 #[allow(dead_code)]
@@ -17,8 +20,13 @@ pub struct ZipfianGenerator {
     items: u64,
     base: u64,
     zipfian_constant: f64,
-    zipfian_parameters: ZipfianParameters,
-    count_for_zeta: u64,
+    alpha: f64,
+    theta: f64,
+    zeta2theta: f64,
+    zipfian_parameters: ArcSwap<ZipfianParameters>,
+    /// Held while the parameters are recomputed, so that one caller at a time
+    /// sums the new terms. Readers never wait for it.
+    recompute: Mutex<()>,
     allow_item_count_decrease: bool,
 }
 
@@ -34,6 +42,10 @@ fn zeta_2(n: u64, theta: f64) -> f64 {
     zeta_4(0, n, theta, 0.0)
 }
 
+fn eta(items: u64, theta: f64, zeta2theta: f64, zetan: f64) -> f64 {
+    (1.0 - (2.0 / items as f64).powf(1.0 - theta)) / (1.0 - zeta2theta / zetan)
+}
+
 impl ZipfianGenerator {
     pub fn from_items(items: u64) -> Self {
         Self::from_range(0, items - 1)
@@ -57,62 +69,69 @@ impl ZipfianGenerator {
         let zeta2theta = zeta_2(2, theta);
         let items = max - min + 1;
         let zipfian_parameters = ZipfianParameters {
-            alpha: 1.0 / (1.0 - theta),
+            items,
             zetan,
-            eta: (1.0 - (2.0 / items as f64).powf(1.0 - theta)) / (1.0 - zeta2theta / zetan),
-            theta,
-            zeta2theta,
+            eta: eta(items, theta, zeta2theta, zetan),
         };
         Self {
             items,
             base: min,
             zipfian_constant,
-            zipfian_parameters,
-            count_for_zeta: items,
+            alpha: 1.0 / (1.0 - theta),
+            theta,
+            zeta2theta,
+            zipfian_parameters: ArcSwap::from_pointee(zipfian_parameters),
+            recompute: Mutex::new(()),
             allow_item_count_decrease: false,
         }
     }
 
-    fn next_long(&self, item_count: u64, rng: &mut SmallRng) -> u64 {
-        if item_count != self.count_for_zeta {
-            /*
-            if item_count > self.count_for_zeta {
-                warn!("incrementally recomputing Zipfian distribtion (increase)");
-                self.zipfian_parameters.zetan = zeta_4(
-                    self.count_for_zeta,
-                    item_count,
-                    self.zipfian_parameters.theta,
-                    self.zipfian_parameters.zetan,
-                );
-            }
-            if item_count < self.count_for_zeta && self.allow_item_count_decrease {
-                warn!("incrementally recomputing Zipfian distribtion (decrease). This is slow and should be avoided.");
-                self.zipfian_parameters.zetan = zeta_2(item_count, self.zipfian_parameters.theta);
-            }
+    fn parameters(&self) -> ZipfianParameters {
+        **self.zipfian_parameters.load()
+    }
 
-            self.count_for_zeta = item_count;
-            self.zipfian_parameters.eta = (1.0
-                - (2.0 / self.items as f64).powf(1.0 - self.zipfian_parameters.theta))
-                / (1.0 - self.zipfian_parameters.zeta2theta / self.zipfian_parameters.zetan);
-            */
-            todo!("change item count after creating zipfian is not yet supported");
+    /// Brings `zetan` and `eta` up to `item_count` items. Growing only sums
+    /// the new terms; shrinking starts over, so it is only done if allowed.
+    fn update_item_count(&self, item_count: u64) -> ZipfianParameters {
+        let _lock = self.recompute.lock().unwrap();
+        let current = self.parameters();
+        let zetan = if item_count > current.items {
+            zeta_4(current.items, item_count, self.theta, current.zetan)
+        } else if item_count < current.items && self.allow_item_count_decrease {
+            zeta_2(item_count, self.theta)
+        } else {
+            return current;
+        };
+        let updated = ZipfianParameters {
+            items: item_count,
+            zetan,
+            eta: eta(item_count, self.theta, self.zeta2theta, zetan),
+        };
+        self.zipfian_parameters.store(Arc::new(updated));
+        updated
+    }
+
+    /// A value among the first `item_count` items, which may differ from the
+    /// count the generator was created with, e.g. to follow inserts.
+    pub fn next_long(&self, item_count: u64, rng: &mut SmallRng) -> u64 {
+        let mut parameters = self.parameters();
+        if item_count > parameters.items || (item_count < parameters.items && self.allow_item_count_decrease) {
+            parameters = self.update_item_count(item_count);
         }
+        let ZipfianParameters { zetan, eta, .. } = parameters;
 
         let u = rng.gen::<f64>();
-        let uz = u * self.zipfian_parameters.zetan;
+        let uz = u * zetan;
 
         if uz < 1.0 {
             return self.base;
         }
 
-        if uz < 1.0 + (0.5_f64).powf(self.zipfian_parameters.theta) {
+        if uz < 1.0 + (0.5_f64).powf(self.theta) {
             return self.base + 1;
         }
 
-        self.base
-            + (item_count as f64
-                * (self.zipfian_parameters.eta * u - self.zipfian_parameters.eta + 1.0)
-                    .powf(self.zipfian_parameters.alpha)) as u64
+        self.base + (item_count as f64 * (eta * u - eta + 1.0).powf(self.alpha)) as u64
     }
 }
 
@@ -123,8 +142,12 @@ impl Generator<u64> for ZipfianGenerator {
 }
 
 impl NumberGenerator<u64> for ZipfianGenerator {
+    /// The mean of the Zipfian distribution over the current item count.
+    /// Takes time linear in the item count.
     fn mean(&self) -> u64 {
-        todo!("implement ZipfianGenerator::mean")
+        let ZipfianParameters { items, zetan, .. } = self.parameters();
+        let sum: f64 = (0..items).map(|i| i as f64 / (i as f64 + 1.0).powf(self.theta)).sum();
+        self.base + (sum / zetan).round() as u64
     }
 }
 
@@ -147,4 +170,45 @@ mod tests {
         }
         println!("{:?}", result);
     }
+
+    #[test]
+    fn test_mean() {
+        let zipfian = ZipfianGenerator::from_range(10, 1009);
+        let mut rng = SmallRng::seed_from_u64(1);
+        let samples = 200_000;
+        let total: u64 = (0..samples).map(|_| zipfian.next_value(&mut rng)).sum();
+        let sampled = total as f64 / samples as f64;
+        let mean = zipfian.mean() as f64;
+        assert!((sampled - mean).abs() / mean < 0.05, "sampled {} vs mean {}", sampled, mean);
+    }
+
+    #[test]
+    fn test_item_count_growth() {
+        let zipfian = ZipfianGenerator::from_items(100);
+        let mut rng = SmallRng::seed_from_u64(1);
+        let max = (0..100_000).map(|_| zipfian.next_long(200, &mut rng)).max().unwrap();
+        assert!((100..200).contains(&max));
+        let zetan = zipfian.parameters().zetan;
+        assert!((zetan - zeta_2(200, ZIPFIAN_CONSTANT)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_concurrent_growth_keeps_parameters_consistent() {
+        let zipfian = ZipfianGenerator::from_items(100);
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let zipfian = &zipfian;
+                scope.spawn(move || {
+                    let mut rng = SmallRng::seed_from_u64(thread);
+                    for items in (100..5000).step_by(4) {
+                        zipfian.next_long(items + thread, &mut rng);
+                    }
+                });
+            }
+            for _ in 0..10_000 {
+                let ZipfianParameters { items, zetan, eta: read_eta } = zipfian.parameters();
+                assert_eq!(read_eta, eta(items, zipfian.theta, zipfian.zeta2theta, zetan));
+            }
+        });
+    }
 }
@@ -1,28 +1,29 @@
 use crate::db::DB;
 use crate::workload::Workload;
 use anyhow::{bail, Result};
-use futures::channel::mpsc::{UnboundedSender, UnboundedReceiver};
 use properties::Properties;
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
 use std::fs;
-use std::rc::Rc;
-use std::thread;
-use std::time::{Instant, Duration};
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
-use workload::CoreWorkload;
-use std::sync::{Arc};
-use tokio::sync::Mutex;
-use std::borrow::BorrowMut;
-use tokio::sync::mpsc::{channel, Sender};
+use trace::TraceRecord;
+use workload::{CoreWorkload, TraceWorkload};
+use std::sync::Arc;
 use tokio::sync::Semaphore;
-use tokio::sync::mpsc::*;
-use hdrhistogram::*;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
 
 pub mod db;
+pub mod distributed;
 pub mod generator;
 pub mod properties;
 //pub mod sqlite;
 pub mod postgres;
 //pub mod rocksdb;
+pub mod stats;
+#[cfg(test)]
+mod testing;
+pub mod trace;
 pub mod workload;
 
 #[derive(StructOpt, Debug)]
@@ -36,10 +37,43 @@ pub struct Opt {
     workload: String,
     #[structopt(short, long, default_value = "1")]
     threads: usize,
-    #[structopt(short, long, default_value = "300")]
+    /// Timeout of every database call, in ms (`-t` is `--threads`)
+    #[structopt(short = "T", long, default_value = "300")]
     timeout: u64,
     #[structopt(short, long, default_value = "100")]
     retries: u64,
+    /// Drop and recreate the table before `load`
+    #[structopt(long)]
+    recreate: bool,
+    /// Address the coordinator listens on and workers connect to
+    #[structopt(long, default_value = "127.0.0.1:7878")]
+    address: String,
+    /// Number of workers the coordinator waits for
+    #[structopt(long, default_value = "1")]
+    workers: usize,
+    /// Trace file `run` records its operations to
+    #[structopt(long)]
+    record: Option<String>,
+    /// Trace file `replay` issues the operations of
+    #[structopt(long)]
+    trace: Option<String>,
+    /// Replay as fast as possible instead of on the recorded schedule
+    #[structopt(long)]
+    max_speed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestKind {
+    /// A single database operation.
+    Operation,
+    /// The `COMMIT` of a transaction.
+    Commit,
+    /// A transaction rolled back before it could commit.
+    Abort,
+    /// The final result of an operation, after any retries.
+    Outcome,
+    /// An insert `load` gave up on, and the `rows` it would have inserted.
+    LostInsert { rows: u64 },
 }
 
 #[derive(Debug)]
@@ -47,52 +81,313 @@ pub struct Request {
     latency: u128,
     success: bool,
     is_retry: bool,
+    kind: RequestKind,
+    serialization_failure: bool,
+    /// What kind of error the request failed with, if it's counted as one.
+    error: Option<db::ErrorKind>,
 }
 
 pub type SenderType = Arc<tokio::sync::mpsc::UnboundedSender<Request>>;
 
-async fn load(wl: Arc<CoreWorkload>, db: db::DBType, operation_count: usize) {
+/// The share of `total` that worker `idx` of `workers` gets. The first
+/// `total % workers` workers take one extra, so the shares add up to `total`.
+fn partition(total: usize, workers: usize, idx: usize) -> usize {
+    total / workers + usize::from(idx < total % workers)
+}
+
+/// Inserts the `record_count` records from `insert_start` on.
+async fn load(wl: Arc<dyn Workload>, db: db::DBType, mut rng: SmallRng, insert_start: u64, record_count: usize, batch_size: usize) {
     let mut joins = vec![];
     // Use the semaphore to make sure we don't issue too many requests
     // For each "thread", we can have X requests in flight, so 10 threads = 10x concurrent reqs
     let semaphore = Arc::new(Semaphore::new(300));
-    for _ in 0..operation_count {
+    let mut remaining = record_count;
+    let mut keynum = insert_start;
+    while remaining > 0 {
+        let count = remaining.min(batch_size);
+        remaining -= count;
+        let start = keynum;
+        keynum += count as u64;
         let db = db.clone();
         let wl = wl.clone();
+        let mut rng = SmallRng::from_rng(&mut rng).unwrap();
         let permit = semaphore.clone().acquire_owned().await.unwrap();
         let join = tokio::task::spawn(async move {
-            wl.do_insert(db.clone()).await;
+            if count == 1 {
+                wl.do_insert(db.clone(), &mut rng, start).await;
+            } else {
+                wl.do_insert_batch(db.clone(), &mut rng, start, count).await;
+            }
             drop(permit);
         });
         joins.push(join);
     }
 
-    for join in joins {
-        join.await.unwrap();
-    }
+    completed(joins).await
 }
 
-async fn run(wl: Arc<CoreWorkload>, db: db::DBType, operation_count: usize) {
+/// Runs `operation_count` operations.
+async fn run(wl: Arc<dyn Workload>, db: db::DBType, mut rng: SmallRng, operation_count: usize, ops_per_transaction: usize) {
 
     let mut joins = vec![];
     // Use the semaphore to make sure we don't issue too many requests
     let semaphore = Arc::new(Semaphore::new(1000));
-    for _ in 0..operation_count {
+    let mut remaining = operation_count;
+    while remaining > 0 {
+        let count = remaining.min(ops_per_transaction.max(1));
+        remaining -= count;
         let db = db.clone();
         let wl = wl.clone();
+        let mut rng = SmallRng::from_rng(&mut rng).unwrap();
         let permit = semaphore.clone().acquire_owned().await.unwrap();
         let join = tokio::task::spawn(async move {
-            wl.do_transaction(db.clone()).await;
+            if ops_per_transaction == 0 {
+                wl.do_transaction(db.clone(), &mut rng).await;
+            } else {
+                wl.do_transactions_in_txn(db.clone(), &mut rng, count).await;
+            }
             drop(permit);
         });
         joins.push(join);
     }
 
+    completed(joins).await
+}
+
+/// Replays every `stride`-th record of `trace` from `first` on, on the
+/// schedule recorded relative to `start` unless `max_speed`.
+#[allow(clippy::too_many_arguments)]
+async fn replay(
+    wl: Arc<CoreWorkload>,
+    db: db::DBType,
+    mut rng: SmallRng,
+    trace: Arc<Vec<TraceRecord>>,
+    first: usize,
+    stride: usize,
+    start: tokio::time::Instant,
+    max_speed: bool,
+) {
+    let mut joins = vec![];
+    let semaphore = Arc::new(Semaphore::new(1000));
+    for idx in (first..trace.len()).step_by(stride) {
+        if !max_speed {
+            tokio::time::sleep_until(start + Duration::from_micros(trace[idx].offset_us)).await;
+        }
+        let db = db.clone();
+        let wl = wl.clone();
+        let trace = trace.clone();
+        let mut rng = SmallRng::from_rng(&mut rng).unwrap();
+        let permit = semaphore.clone().acquire_owned().await.unwrap();
+        let join = tokio::task::spawn(async move {
+            wl.replay(db, &mut rng, &trace[idx].op).await;
+            drop(permit);
+        });
+        joins.push(join);
+    }
+
+    completed(joins).await
+}
+
+/// Seed `seed` split into one per `idx`, so that threads and workers each
+/// draw their own reproducible stream.
+fn derive_seed(seed: u64, idx: usize) -> u64 {
+    // splitmix64, so that neighbouring seeds and indexes give unrelated
+    // streams.
+    let mut z = seed.wrapping_add((idx as u64 + 1).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Waits for every task. The operations of tasks that panicked are missing
+/// from the stats, which `report` catches.
+async fn completed(joins: Vec<tokio::task::JoinHandle<()>>) {
     for join in joins {
-        join.await.unwrap();
+        let _ = join.await;
+    }
+}
+
+/// Creates the workload's table along with its secondary indexes.
+async fn create_table(db: &mut db::DBType, props: &Properties) -> Result<()> {
+    db.create_table(&props.table).await?;
+    for field in &props.indexed_fields {
+        db.create_index(&props.table, field).await?;
     }
+    Ok(())
+}
+
+/// Checks the table before a phase: `load` creates it, `run` makes sure it
+/// has been loaded.
+async fn prepare_phase(cmd: &str, opt: &Opt, props: &Properties, db: &mut db::DBType) -> Result<()> {
+    let table = &props.table;
+    if cmd == "load" {
+        if props.record_count > 0 && props.insert_start + props.load_count() > props.record_count {
+            bail!("insertstart + insertcount is past recordcount {}", props.record_count);
+        }
+        if opt.recreate {
+            db.drop_table(table).await?;
+        }
+        create_table(db, props).await?;
+    } else {
+        let count = db.count(table).await?;
+        if count < props.record_count {
+            bail!("{} has {} records, fewer than recordcount {}; load it first", table, count, props.record_count);
+        }
+    }
+    Ok(())
 }
 
+/// The number of records `load` inserts, or of operations `run` performs.
+fn phase_count(cmd: &str, props: &Properties) -> usize {
+    if cmd == "load" {
+        props.load_count() as usize
+    } else {
+        props.operation_count as usize
+    }
+}
+
+/// A fresh workload and stats thread for one `load` or `run` phase, so that
+/// each phase starts where a separate invocation would.
+struct Phase {
+    /// The workload `load` and `run` go through, and the core workload that
+    /// runs its operations and `replay`'s.
+    wl: Arc<dyn Workload>,
+    core: Arc<CoreWorkload>,
+    /// What the stats thread `execute` spawns receives, so that its intervals
+    /// start with the phase.
+    stats: UnboundedReceiver<Request>,
+    /// The records `replay` issues.
+    trace: Arc<Vec<TraceRecord>>,
+}
+
+impl Phase {
+    fn new(cmd: &str, opt: &Opt, props: &Properties) -> Result<Self> {
+        let trace = match (cmd, &opt.trace) {
+            ("replay", Some(path)) => trace::read(path)?,
+            ("replay", None) => bail!("replay needs --trace"),
+            _ => vec![],
+        };
+        let (tx, stats) = unbounded_channel();
+        let mut core = CoreWorkload::new(props, opt, Arc::new(tx))?;
+        if let ("run", Some(path)) = (cmd, &opt.record) {
+            core.record_to(path)?;
+        }
+        let core = Arc::new(core);
+        let wl: Arc<dyn Workload> = match &props.workload[..] {
+            "core" => core.clone(),
+            "trace" => Arc::new(TraceWorkload::new(props, core.clone())?),
+            workload => bail!("unknown workload {}", workload),
+        };
+        Ok(Phase { wl, core, stats, trace: Arc::new(trace) })
+    }
+
+    /// The number of operations `cmd` performs.
+    fn count(&self, cmd: &str, props: &Properties) -> usize {
+        match cmd {
+            "replay" => self.trace.iter().map(|record| record.op.operations()).sum(),
+            "run" => self.wl.run_count(phase_count(cmd, props)),
+            _ => phase_count(cmd, props),
+        }
+    }
+
+    /// Performs `count` operations of `cmd`, split across `--threads`, and
+    /// returns their stats. `replay` deals the trace's records out to the
+    /// threads in turn instead.
+    async fn execute(self, cmd: &str, opt: &Opt, props: &Properties, db: &db::DBType, count: usize) -> stats::Summary {
+        let batch_size = props.batch_size.max(1) as usize;
+        let ops_per_transaction = props.ops_per_transaction as usize;
+
+        let mut threads = vec![];
+        let mut insert_start = props.insert_start;
+        let stats = stats::spawn(self.stats);
+        self.core.start_recording();
+        let start = tokio::time::Instant::now();
+        for idx in 0..opt.threads {
+            let wl = self.wl.clone();
+            let core = self.core.clone();
+            let cmd = cmd.to_string();
+            let db = db.clone();
+            let count = partition(count, opt.threads, idx);
+            // Each task gets its own rng, split off its thread's, and each
+            // thread loads its own range of keys, so that a seeded load
+            // doesn't depend on how the tasks interleave.
+            let rng = match props.seed {
+                Some(seed) => SmallRng::seed_from_u64(derive_seed(seed, idx)),
+                None => SmallRng::from_entropy(),
+            };
+            let keynum = insert_start;
+            insert_start += count as u64;
+            let trace = self.trace.clone();
+            let max_speed = opt.max_speed;
+            let stride = opt.threads;
+            threads.push(tokio::spawn(async move {
+                match &cmd[..] {
+                    "load" => load(wl.clone(), db, rng, keynum, count, batch_size).await,
+                    "run" => run(wl.clone(), db, rng, count, ops_per_transaction).await,
+                    "replay" => replay(core, db, rng, trace, idx, stride, start, max_speed).await,
+                    _ => unreachable!(),
+                }
+            }));
+        }
+        for t in threads {
+            let _ = t.await;
+        }
+        // Dropping the last sender lets the stats thread print what's left.
+        drop(self.wl);
+        drop(self.core);
+        stats.join().unwrap()
+    }
+}
+
+/// Prints the `[OVERALL]` report of a phase, and fails unless the stats
+/// recorded all of its `expected` operations (rows, for `load`), or if
+/// `load` gave up on some rows. Operations that were issued but failed
+/// count, as upstream counts them.
+fn report(cmd: &str, threads: usize, runtime: u128, expected: usize, summary: &stats::Summary) -> Result<()> {
+    println!("[OVERALL], Phase, {}", cmd);
+    println!("[OVERALL], ThreadCount, {}", threads);
+    println!("[OVERALL], RunTime(ms), {}", runtime);
+    let recorded = summary.operations as usize;
+    if cmd == "load" {
+        let rows = recorded.saturating_sub(summary.lost_inserts as usize);
+        println!("[OVERALL], Rows, {}", rows);
+        println!("[OVERALL], Throughput(rows/sec), {}", rows as f64 / (runtime as f64 / 1000.0));
+        println!("[OVERALL], UnrecoverableInserts, {}", summary.lost_inserts);
+    } else {
+        println!("[OVERALL], Operations, {}", recorded);
+        println!("[OVERALL], Throughput(ops/sec), {}", recorded as f64 / (runtime as f64 / 1000.0));
+    }
+    summary.print_overall();
+    if recorded != expected {
+        bail!("{} performed {} of {} operations", cmd, recorded, expected);
+    }
+    if summary.lost_inserts > 0 {
+        bail!("load gave up inserting {} of {} rows", summary.lost_inserts, expected);
+    }
+    Ok(())
+}
+
+/// Runs one command against the database in this process.
+async fn run_command(cmd: &str, opt: &Opt, props: &Properties, db: &mut db::DBType) -> Result<()> {
+    match cmd {
+        "create" => create_table(db, props).await?,
+        "drop" => db.drop_table(&props.table).await?,
+        "truncate" => db.truncate_table(&props.table).await?,
+        "count" => println!("{}", db.count(&props.table).await?),
+        _ => {
+            prepare_phase(cmd, opt, props, db).await?;
+            let phase = Phase::new(cmd, opt, props)?;
+            let expected = phase.count(cmd, props);
+            let start = Instant::now();
+            let summary = phase.execute(cmd, opt, props, db, expected).await;
+            report(cmd, opt.threads, start.elapsed().as_millis(), expected, &summary)?;
+        }
+    }
+    Ok(())
+}
+
+const COMMANDS: &[&str] = &["create", "drop", "truncate", "count", "load", "run", "replay"];
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let opt = Opt::from_args();
@@ -101,105 +396,198 @@ async fn main() -> Result<()> {
 
     let props: Properties = toml::from_str(&raw_props)?;
 
-    let props = Arc::new(props);
-    let (tx, mut rx): (tokio::sync::mpsc::UnboundedSender<Request>, tokio::sync::mpsc::UnboundedReceiver<Request>) = unbounded_channel();
-    let tx = Arc::new(tx);
-
-    let wl = Arc::new(CoreWorkload::new(&props, &opt, tx.clone()));
-
-    if opt.commands.is_empty() {
-        bail!("no command specified");
-    }
-
-    // Create a thread to track latency / failure stats
-    std::thread::spawn(move || {
-        let waker = futures::task::noop_waker();
-        let mut cx = std::task::Context::from_waker(&waker);
-
-        let mut histogram = Histogram::<u64>::new_with_bounds(1, 60 * 60 * 1000, 2).unwrap();
-
-        let mut fail_count = 0;
-        let mut retry_count = 0;
-        let mut total = 0;
-        let mut now = std::time::Instant::now();
-        while true {
-            match rx.poll_recv(&mut cx) {
-                core::task::Poll::Ready(Some(msg)) => {
-                    //dbg!(&msg);
-                    if !msg.success {
-                        fail_count += 1;
-                    } else {
-                        // all failure latency == timeout so don't bother tracking those
-                        histogram.record(msg.latency as u64).unwrap();
-                    }
-
-                    if msg.is_retry {
-                        retry_count += 1;
-                    }
-                    total += 1;
-                },
-                _ => {
+    let (mode, commands) = match opt.commands.first().map(|cmd| &cmd[..]) {
+        None => bail!("no command specified"),
+        Some(mode @ ("coordinator" | "worker")) => (Some(mode), &opt.commands[1..]),
+        Some(_) => (None, &opt.commands[..]),
+    };
+    for cmd in commands {
+        if !COMMANDS.contains(&&cmd[..]) {
+            bail!("invalid command: {}", cmd);
+        }
+    }
 
-                }
+    let mut db = db::create_db(&opt.database, &props).await?;
+    db.init().await?;
+
+    match mode {
+        Some("coordinator") => distributed::coordinator(&opt, &props, &mut db, commands).await,
+        Some(_) => {
+            if !commands.is_empty() {
+                bail!("a worker takes its commands from the coordinator");
             }
+            distributed::worker(&opt, &props, &db).await
+        }
+        None => {
+            // Every command shares the connection pool.
+            for cmd in commands {
+                run_command(cmd, &opt, &props, &mut db).await?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::Ordering::SeqCst;
+    use std::sync::Mutex;
+
+    /// Keeps the records `load` hands it instead of inserting them.
+    struct Inserts {
+        core: Arc<CoreWorkload>,
+        records: Mutex<BTreeMap<String, BTreeMap<String, String>>>,
+    }
+
+    #[async_trait]
+    impl Workload for Inserts {
+        async fn do_insert(&self, db: db::DBType, rng: &mut SmallRng, keynum: u64) {
+            self.do_insert_batch(db, rng, keynum, 1).await;
+        }
 
-            // Emit percentiles every ~ X ms
-            let ellapsed = now.elapsed().as_millis();
-            if ellapsed < 1000 {
-                continue;
+        async fn do_insert_batch(&self, _db: db::DBType, rng: &mut SmallRng, keynum: u64, count: usize) {
+            for keynum in keynum..keynum + count as u64 {
+                let values = self.core.build_values(rng).into_iter().map(|(field, value)| (field.to_string(), value));
+                self.records.lock().unwrap().insert(self.core.build_key_name(keynum), values.collect());
             }
-            // Reset the timer and emit percentiles
-            now = std::time::Instant::now();
-            println!("Success rate: {}, total reqs: {}, retry frac: {}", (total - fail_count) as f64 / total as f64, total as f64,
-            (retry_count) as f64 / total as f64);
+        }
 
-            retry_count = 0;
-            fail_count = 0;
-            total = 0;
+        async fn do_update(&self, _db: db::DBType, _rng: &mut SmallRng) {
+            unreachable!()
+        }
 
-            println!("P25: {}", histogram.value_at_quantile(0.25));
-            println!("P50: {}", histogram.value_at_quantile(0.50));
-            println!("P75: {}", histogram.value_at_quantile(0.75));
-            println!("P99: {}", histogram.value_at_quantile(0.99));
-            histogram.clear();
+        async fn do_transaction(&self, _db: db::DBType, _rng: &mut SmallRng) {
+            unreachable!()
         }
-    });
 
-    let database = opt.database.clone();
-    let thread_operation_count = props.operation_count as usize / opt.threads;
-    let start = Instant::now();
+        async fn do_transactions_in_txn(&self, _db: db::DBType, _rng: &mut SmallRng, _count: usize) {
+            unreachable!()
+        }
+    }
 
-    let mut threads = vec![];
-    let mut db = db::create_db(&database).await.unwrap();
+    /// The records a seeded `load` on four threads inserts.
+    async fn seeded_load(seed: u64) -> BTreeMap<String, BTreeMap<String, String>> {
+        let props = testing::props(&format!("operationcount = 1\nrecordcount = 1000\nbatchsize = 7\nseed = {}", seed));
+        let opt = testing::opt(&["-t", "4"]);
+        let db = db::DBType::new(&props.postgres).await.unwrap();
+        // Its own core workload, since the phase's stats only finish once
+        // every sender is gone.
+        let (tx, _rx) = unbounded_channel();
+        let core = Arc::new(CoreWorkload::new(&props, &opt, Arc::new(tx)).unwrap());
+        let inserts = Arc::new(Inserts { core, records: Mutex::default() });
+        let Phase { core, stats, trace, .. } = Phase::new("load", &opt, &props).unwrap();
+        let phase = Phase { wl: inserts.clone(), core, stats, trace };
+        phase.execute("load", &opt, &props, &db, 1000).await;
+        let records = std::mem::take(&mut *inserts.records.lock().unwrap());
+        records
+    }
 
-    db.init().await.unwrap();
+    /// Runs the mix until `left` runs out, then stops without a word, like
+    /// a task that returns early.
+    struct StopsEarly {
+        core: Arc<CoreWorkload>,
+        left: std::sync::atomic::AtomicUsize,
+    }
 
-    for _ in 0..opt.threads {
-        //let database = database.clone();
-        let tx = tx.clone();
-        let wl = wl.clone();
-        let cmd = opt.commands[0].clone();
-        let db = db.clone();
-        //let db = db::create_db(&database).await.unwrap();
-        threads.push(tokio::spawn(async move {
-            //let mut db = db::create_db(&database).await.unwrap();
-            //db.init().await.unwrap();
-
-            match &cmd[..] {
-                "load" => load(wl.clone(), db, thread_operation_count as usize).await,
-                "run" => run(wl.clone(), db, thread_operation_count as usize).await,
-                cmd => panic!("invalid command: {}", cmd),
-            };
-        }));
+    #[async_trait]
+    impl Workload for StopsEarly {
+        async fn do_insert(&self, _db: db::DBType, _rng: &mut SmallRng, _keynum: u64) {
+            unreachable!()
+        }
+
+        async fn do_insert_batch(&self, _db: db::DBType, _rng: &mut SmallRng, _keynum: u64, _count: usize) {
+            unreachable!()
+        }
+
+        async fn do_update(&self, _db: db::DBType, _rng: &mut SmallRng) {
+            unreachable!()
+        }
+
+        async fn do_transaction(&self, db: db::DBType, rng: &mut SmallRng) {
+            let left = self.left.fetch_update(SeqCst, SeqCst, |left| left.checked_sub(1));
+            if left.is_ok() {
+                self.core.do_transaction(db, rng).await;
+            }
+        }
+
+        async fn do_transactions_in_txn(&self, _db: db::DBType, _rng: &mut SmallRng, _count: usize) {
+            unreachable!()
+        }
     }
-    for t in threads {
-        let _ = t.await;
+
+    /// Reports a `run` of 100 operations against a server that isn't there,
+    /// of which the workload only performs `performed`.
+    async fn run_stopping_after(performed: usize) -> Result<()> {
+        let props = testing::unreachable_props("operationcount = 100\nrecordcount = 10");
+        let opt = testing::opt(&["-t", "4"]);
+        let db = db::DBType::new(&props.postgres).await.unwrap();
+        let Phase { core, stats, trace, .. } = Phase::new("run", &opt, &props).unwrap();
+        let wl = Arc::new(StopsEarly { core: core.clone(), left: performed.into() });
+        let phase = Phase { wl, core, stats, trace };
+        let summary = phase.execute("run", &opt, &props, &db, 100).await;
+        report("run", 4, 1, 100, &summary)
     }
-    let runtime = start.elapsed().as_millis();
-    println!("[OVERALL], ThreadCount, {}", opt.threads);
-    println!("[OVERALL], RunTime(ms), {}", runtime);
-    let throughput = props.operation_count as f64 / (runtime as f64 / 1000.0);
-    println!("[OVERALL], Throughput(ops/sec), {}", throughput);
 
-    Ok(())
+    #[tokio::test]
+    async fn test_report_counts_performed_operations() {
+        // Every operation fails, but they all count.
+        run_stopping_after(100).await.unwrap();
+        let err = run_stopping_after(90).await.unwrap_err();
+        assert_eq!(err.to_string(), "run performed 90 of 100 operations");
+    }
+
+    /// The operations a seeded `run` on four threads records, in a fixed
+    /// order, with the keys of its inserts left out.
+    async fn seeded_run(seed: u64) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!("ycsb-seeded-run-{}-{}.jsonl", std::process::id(), seed));
+        let path = path.to_str().unwrap();
+        let props = testing::unreachable_props(&format!(
+            "operationcount = 200\nrecordcount = 100\nreadproportion = 0.4\nupdateproportion = 0.3\n\
+             insertproportion = 0.3\nseed = {}",
+            seed
+        ));
+        let opt = testing::opt(&["-t", "4", "--record", path]);
+        let db = db::DBType::new(&props.postgres).await.unwrap();
+        Phase::new("run", &opt, &props).unwrap().execute("run", &opt, &props, &db, 200).await;
+        let mut ops: Vec<_> = trace::read(path)
+            .unwrap()
+            .into_iter()
+            .map(|record| match record.op {
+                trace::TracedOperation::Insert { sizes, .. } => {
+                    trace::TracedOperation::Insert { key: String::new(), sizes }
+                }
+                op => op,
+            })
+            .map(|op| serde_json::to_string(&op).unwrap())
+            .collect();
+        std::fs::remove_file(path).unwrap();
+        ops.sort();
+        ops
+    }
+
+    #[tokio::test]
+    async fn test_seeded_run_is_reproducible() {
+        let first = seeded_run(42).await;
+        assert_eq!(first.len(), 200);
+        assert_eq!(first, seeded_run(42).await);
+        assert_ne!(first, seeded_run(43).await);
+    }
+
+    #[tokio::test]
+    async fn test_seeded_load_is_reproducible() {
+        let first = seeded_load(42).await;
+        assert_eq!(first.len(), 1000);
+        assert_eq!(first, seeded_load(42).await);
+        assert_ne!(first, seeded_load(43).await);
+    }
+
+    #[test]
+    fn test_partition() {
+        let shares: Vec<_> = (0..4).map(|idx| partition(10, 4, idx)).collect();
+        assert_eq!(shares, vec![3, 3, 2, 2]);
+        assert_eq!((0..3).map(|idx| partition(2, 3, idx)).sum::<usize>(), 2);
+    }
 }
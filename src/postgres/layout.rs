@@ -0,0 +1,388 @@
+use anyhow::{anyhow, Result};
+use bytes::{BufMut, BytesMut};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use tokio_postgres::types::{accepts, to_sql_checked, IsNull, ToSql, Type};
+
+use super::PRIMARY_KEY;
+
+const DATA_COLUMN: &str = "data";
+const FIELD_COUNT: usize = 10;
+
+/// How a record's fields are stored in the table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    /// The classic YCSB schema: one `VARCHAR` column per field.
+    Columns,
+    /// All fields in a single `JSONB` document.
+    Jsonb,
+    /// All fields serialized into a single opaque `BYTEA` value. Updates
+    /// replace the whole value, so they have to carry every field.
+    Blob,
+}
+
+impl std::str::FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "columns" => Ok(Layout::Columns),
+            "jsonb" => Ok(Layout::Jsonb),
+            "blob" => Ok(Layout::Blob),
+            _ => Err(anyhow!("unknown layout {}", s)),
+        }
+    }
+}
+
+/// A bind parameter, which can also be rendered as a literal for the simple
+/// query protocol.
+#[derive(Debug)]
+pub enum Param<'a> {
+    Null,
+    Text(&'a str),
+    Int(i64),
+    Json(Jsonb<'a>),
+    Bytes(&'a [u8]),
+}
+
+const NULL: Option<&str> = None;
+
+impl<'a> Param<'a> {
+    pub fn as_sql(&self) -> &(dyn ToSql + Sync) {
+        match self {
+            Param::Null => &NULL,
+            Param::Text(v) => v,
+            Param::Int(v) => v,
+            Param::Json(v) => v,
+            Param::Bytes(v) => v,
+        }
+    }
+
+    /// Relies on `standard_conforming_strings`, which has been the server
+    /// default since 9.1.
+    pub fn literal(&self) -> String {
+        match self {
+            Param::Null => "NULL".to_string(),
+            Param::Int(v) => v.to_string(),
+            Param::Text(v) | Param::Json(Jsonb(v)) => format!("'{}'", v.replace('\'', "''")),
+            Param::Bytes(v) => {
+                let hex: String = v.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("'\\x{}'", hex)
+            }
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Param<'a> {
+    fn from(v: &'a str) -> Self {
+        Param::Text(v)
+    }
+}
+
+/// A JSON document already serialized to text, sent as `JSONB`.
+#[derive(Debug)]
+pub struct Jsonb<'a>(pub &'a str);
+
+impl ToSql for Jsonb<'_> {
+    fn to_sql(
+        &self,
+        _ty: &Type,
+        out: &mut BytesMut,
+    ) -> std::result::Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        // JSONB binary format version
+        out.put_u8(1);
+        out.put_slice(self.0.as_bytes());
+        Ok(IsNull::No)
+    }
+
+    accepts!(JSONB);
+    to_sql_checked!();
+}
+
+/// A column value read back from the server.
+#[derive(Debug)]
+pub enum Value {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+/// A record's fields encoded for one layout. Holds whatever has to outlive
+/// the bind parameters that point into it.
+pub enum Encoded<'a> {
+    Columns(Vec<Option<&'a str>>),
+    Json(String),
+    Blob(Vec<u8>),
+}
+
+impl<'a> Encoded<'a> {
+    pub fn params(&self) -> Vec<Param<'_>> {
+        match self {
+            Encoded::Columns(values) => values
+                .iter()
+                .map(|v| match v {
+                    Some(v) => Param::Text(v),
+                    None => Param::Null,
+                })
+                .collect(),
+            Encoded::Json(doc) => vec![Param::Json(Jsonb(doc))],
+            Encoded::Blob(blob) => vec![Param::Bytes(blob)],
+        }
+    }
+}
+
+impl Layout {
+    pub fn create_table_sql(&self, table: &str, unlogged: bool, fillfactor: Option<u8>) -> String {
+        let mut columns = vec![format!("{} VARCHAR(64) PRIMARY KEY", PRIMARY_KEY)];
+        match self {
+            Layout::Columns => {
+                for i in 0..FIELD_COUNT {
+                    columns.push(format!("field{} VARCHAR(255)", i));
+                }
+            }
+            Layout::Jsonb => columns.push(format!("{} JSONB NOT NULL", DATA_COLUMN)),
+            Layout::Blob => columns.push(format!("{} BYTEA NOT NULL", DATA_COLUMN)),
+        }
+        let mut sql = format!(
+            "CREATE {}TABLE IF NOT EXISTS {} ({})",
+            if unlogged { "UNLOGGED " } else { "" },
+            table,
+            columns.join(", ")
+        );
+        if let Some(fillfactor) = fillfactor {
+            sql.push_str(&format!(" WITH (fillfactor = {})", fillfactor));
+        }
+        sql
+    }
+
+    /// The columns written, besides the key, for a record with `fields`.
+    pub fn columns<'a>(&self, fields: &[&'a str]) -> Vec<&'a str> {
+        match self {
+            Layout::Columns => fields.to_vec(),
+            Layout::Jsonb | Layout::Blob => vec![DATA_COLUMN],
+        }
+    }
+
+    /// Types of the key and the columns for a record with `fields`, as
+    /// needed by `COPY ... BINARY`.
+    pub fn types(&self, fields: &[&str]) -> Vec<Type> {
+        let mut types = vec![Type::VARCHAR];
+        match self {
+            Layout::Columns => types.extend(fields.iter().map(|_| Type::VARCHAR)),
+            Layout::Jsonb => types.push(Type::JSONB),
+            Layout::Blob => types.push(Type::BYTEA),
+        }
+        types
+    }
+
+    /// Encodes `values` for the columns returned by `columns(fields)`.
+    pub fn encode<'a>(&self, fields: &[&str], values: &'a HashMap<&str, String>) -> Encoded<'a> {
+        match self {
+            Layout::Columns => Encoded::Columns(
+                fields.iter().map(|f| values.get(*f).map(|v| &v[..])).collect(),
+            ),
+            Layout::Jsonb => {
+                let doc: BTreeMap<&str, &str> = values.iter().map(|(k, v)| (*k, &v[..])).collect();
+                Encoded::Json(serde_json::to_string(&doc).unwrap())
+            }
+            Layout::Blob => Encoded::Blob(encode_blob(fields, values)),
+        }
+    }
+
+    /// `UPDATE` binding the key to `$1` and the encoded fields after it.
+    pub fn update_sql(&self, table: &str, fields: &[&str]) -> Result<String> {
+        match self {
+            Layout::Jsonb => Ok(format!(
+                "UPDATE {} SET {} = {} || $2::jsonb WHERE {} = $1",
+                table, DATA_COLUMN, DATA_COLUMN, PRIMARY_KEY
+            )),
+            Layout::Blob if fields.len() < FIELD_COUNT => Err(anyhow!(
+                "the blob layout only updates whole records, got {} of {} fields",
+                fields.len(),
+                FIELD_COUNT
+            )),
+            Layout::Columns | Layout::Blob => Ok(super::update_sql(table, &self.columns(fields))),
+        }
+    }
+
+    /// The select list that `decode` expects.
+    fn select_list(&self) -> String {
+        match self {
+            Layout::Columns => "*".to_string(),
+            Layout::Jsonb => format!("{}, {}::text AS {}", PRIMARY_KEY, DATA_COLUMN, DATA_COLUMN),
+            Layout::Blob => format!("{}, {}", PRIMARY_KEY, DATA_COLUMN),
+        }
+    }
+
+    /// The expression a field is stored under, which is also what gets
+    /// indexed.
+    fn field_expr(&self, field: &str) -> Result<String> {
+        match self {
+            Layout::Columns => Ok(field.to_string()),
+            Layout::Jsonb => Ok(format!("({}->>'{}')", DATA_COLUMN, field.replace('\'', "''"))),
+            Layout::Blob => Err(anyhow!("fields of the blob layout can't be queried or indexed")),
+        }
+    }
+
+    pub fn read_sql(&self, table: &str) -> String {
+        format!("SELECT {} FROM {} WHERE {} = $1", self.select_list(), table, PRIMARY_KEY)
+    }
+
+    /// Reads `$2` records in key order, starting at the key `$1`.
+    pub fn scan_sql(&self, table: &str) -> String {
+        format!(
+            "SELECT {} FROM {} WHERE {} >= $1 ORDER BY {} LIMIT $2",
+            self.select_list(),
+            table,
+            PRIMARY_KEY,
+            PRIMARY_KEY
+        )
+    }
+
+    /// Reads the first record, in `field` order, whose `field` is at least `$1`.
+    pub fn read_by_field_sql(&self, table: &str, field: &str) -> Result<String> {
+        let expr = self.field_expr(field)?;
+        Ok(format!(
+            "SELECT {} FROM {} WHERE {} >= $1 ORDER BY {} LIMIT 1",
+            self.select_list(),
+            table,
+            expr,
+            expr
+        ))
+    }
+
+    pub fn create_index_sql(&self, table: &str, field: &str) -> Result<String> {
+        Ok(format!(
+            "CREATE INDEX IF NOT EXISTS {}_{}_idx ON {} ({})",
+            table,
+            field,
+            table,
+            self.field_expr(field)?
+        ))
+    }
+
+    /// Unpacks a row returned by `read_sql` into field values.
+    pub fn decode(&self, row: HashMap<String, Value>, result: &mut HashMap<String, String>) -> Result<()> {
+        for (column, value) in row {
+            match (self, column == DATA_COLUMN, value) {
+                (Layout::Jsonb, true, Value::Text(doc)) => {
+                    result.extend(serde_json::from_str::<HashMap<String, String>>(&doc)?);
+                }
+                (Layout::Blob, true, Value::Bytes(blob)) => decode_blob(&blob, result)?,
+                // The simple query protocol returns BYTEA in hex format.
+                (Layout::Blob, true, Value::Text(hex)) => decode_blob(&decode_hex(&hex)?, result)?,
+                (_, _, Value::Text(value)) => {
+                    result.insert(column, value);
+                }
+                (_, _, Value::Bytes(_)) => return Err(anyhow!("unexpected binary column {}", column)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Length-prefixed name/value pairs, in field order.
+fn encode_blob(fields: &[&str], values: &HashMap<&str, String>) -> Vec<u8> {
+    let mut blob = vec![];
+    for field in fields {
+        if let Some(value) = values.get(field) {
+            for s in [field.as_bytes(), value.as_bytes()] {
+                blob.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                blob.extend_from_slice(s);
+            }
+        }
+    }
+    blob
+}
+
+fn decode_blob(mut blob: &[u8], result: &mut HashMap<String, String>) -> Result<()> {
+    fn next(blob: &mut &[u8]) -> Result<String> {
+        if blob.len() < 4 {
+            return Err(anyhow!("truncated blob"));
+        }
+        let len = u32::from_le_bytes(blob[..4].try_into().unwrap()) as usize;
+        if blob.len() < 4 + len {
+            return Err(anyhow!("truncated blob"));
+        }
+        let s = String::from_utf8(blob[4..4 + len].to_vec())?;
+        *blob = &blob[4 + len..];
+        Ok(s)
+    }
+    while !blob.is_empty() {
+        let field = next(&mut blob)?;
+        result.insert(field, next(&mut blob)?);
+    }
+    Ok(())
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    let hex = hex.strip_prefix("\\x").ok_or_else(|| anyhow!("not a hex bytea"))?;
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err(anyhow!("malformed hex bytea"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blob_round_trip() {
+        let mut values = HashMap::new();
+        values.insert("field0", "it's".to_string());
+        values.insert("field1", String::new());
+        let blob = encode_blob(&["field0", "field1"], &values);
+
+        let mut result = HashMap::new();
+        decode_blob(&blob, &mut result).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result["field0"], "it's");
+        assert_eq!(result["field1"], "");
+
+        let literal = Param::Bytes(&blob).literal();
+        let mut result = HashMap::new();
+        decode_blob(&decode_hex(&literal[1..literal.len() - 1]).unwrap(), &mut result).unwrap();
+        assert_eq!(result["field0"], "it's");
+
+        // Cut off in the middle of the last field's name.
+        assert!(decode_blob(&blob[..blob.len() - 6], &mut HashMap::new()).is_err());
+        assert!(decode_hex("\\x0").is_err());
+        assert!(decode_hex("\\x0é").is_err());
+        assert!(decode_hex("\\xzz").is_err());
+
+        assert!(Layout::Blob.update_sql("usertable", &["field0"]).is_err());
+        let fields: Vec<String> = (0..FIELD_COUNT).map(|i| format!("field{}", i)).collect();
+        let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+        assert_eq!(
+            Layout::Blob.update_sql("usertable", &fields).unwrap(),
+            "UPDATE usertable SET data = $2 WHERE y_id = $1"
+        );
+    }
+
+    #[test]
+    fn test_create_table_sql() {
+        assert_eq!(
+            Layout::Jsonb.create_table_sql("usertable", true, Some(70)),
+            "CREATE UNLOGGED TABLE IF NOT EXISTS usertable (y_id VARCHAR(64) PRIMARY KEY, data JSONB NOT NULL) WITH (fillfactor = 70)"
+        );
+        assert!(Layout::Columns
+            .create_table_sql("usertable", false, None)
+            .starts_with("CREATE TABLE IF NOT EXISTS usertable (y_id VARCHAR(64) PRIMARY KEY, field0 VARCHAR(255),"));
+    }
+
+    #[test]
+    fn test_index_sql() {
+        assert_eq!(
+            Layout::Columns.create_index_sql("usertable", "field3").unwrap(),
+            "CREATE INDEX IF NOT EXISTS usertable_field3_idx ON usertable (field3)"
+        );
+        assert_eq!(
+            Layout::Jsonb.read_by_field_sql("usertable", "field3").unwrap(),
+            "SELECT y_id, data::text AS data FROM usertable WHERE (data->>'field3') >= $1 ORDER BY (data->>'field3') LIMIT 1"
+        );
+        assert!(Layout::Blob.create_index_sql("usertable", "field3").is_err());
+    }
+}
@@ -4,6 +4,10 @@ fn zero_u64() -> u64 {
     0
 }
 
+fn one_u64() -> u64 {
+    1
+}
+
 fn thread_count_default() -> u64 {
     200
 }
@@ -12,6 +16,18 @@ fn field_length_distribution_default() -> String {
     "constant".to_string()
 }
 
+fn field_length_histogram_default() -> String {
+    "hist.txt".to_string()
+}
+
+fn value_generator_default() -> String {
+    "alphanumeric".to_string()
+}
+
+fn value_compression_ratio_default() -> f64 {
+    2.0
+}
+
 fn request_distribution_default() -> String {
     "uniform".to_string()
 }
@@ -40,12 +56,186 @@ fn read_modify_write_proportion_default() -> f64 {
     0.0
 }
 
-#[derive(Deserialize, Debug)]
+fn indexed_read_proportion_default() -> f64 {
+    0.0
+}
+
+fn max_scan_length_default() -> u64 {
+    1000
+}
+
+fn scan_length_distribution_default() -> String {
+    "uniform".to_string()
+}
+
+fn postgres_connect_timeout_default() -> u64 {
+    360
+}
+
+fn postgres_pool_size_default() -> usize {
+    200
+}
+
+fn postgres_statement_mode_default() -> String {
+    "prepared".to_string()
+}
+
+fn postgres_batch_mode_default() -> String {
+    "copy".to_string()
+}
+
+fn postgres_layout_default() -> String {
+    "columns".to_string()
+}
+
+fn table_default() -> String {
+    "usertable".to_string()
+}
+
+fn retry_policy_default() -> String {
+    "fixed".to_string()
+}
+
+fn retry_backoff_default() -> u64 {
+    10
+}
+
+fn retry_max_backoff_default() -> u64 {
+    1000
+}
+
+fn batch_size_default() -> u64 {
+    1
+}
+
+fn insertion_retry_interval_default() -> u64 {
+    3
+}
+
+fn insert_order_default() -> String {
+    "hashed".to_string()
+}
+
+fn zero_padding_default() -> usize {
+    1
+}
+
+fn key_prefix_default() -> String {
+    "user".to_string()
+}
+
+fn workload_default() -> String {
+    "core".to_string()
+}
+
+fn trace_rate_default() -> f64 {
+    1.0
+}
+
+/// Connection settings for the Postgres backend, read from the `[postgres]`
+/// table (or `postgres.*` keys) of the workload file.
+///
+/// Anything left unset here falls back to the usual libpq environment
+/// variables (`PGHOST`, `PGPORT`, `PGUSER`, `PGPASSWORD`, `PGDATABASE`).
+#[derive(Deserialize, Debug, Clone)]
+pub struct PostgresProperties {
+    /// A `postgresql://` URL or `key=value` connection string. `user`,
+    /// `password` and `dbname` below override the corresponding parts of
+    /// it, and the environment only fills in what it leaves out; `host` and
+    /// `port` can't be combined with it.
+    pub url: Option<String>,
+    /// Comma separated list of hosts. Entries starting with `/` are treated
+    /// as directories containing the server's Unix socket.
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub dbname: Option<String>,
+    /// `any` or `read-write`; used to pick a primary out of several hosts.
+    pub target_session_attrs: Option<String>,
+    /// Connect and TCP user timeout, in seconds.
+    #[serde(default = "postgres_connect_timeout_default")]
+    pub connect_timeout: u64,
+    #[serde(default = "postgres_pool_size_default")]
+    pub pool_size: usize,
+    /// `prepared` (cached per connection), `unprepared` or `simple`.
+    #[serde(default = "postgres_statement_mode_default")]
+    pub statement_mode: String,
+    /// How batched loads are sent: `copy` or multi-row `insert`.
+    #[serde(default = "postgres_batch_mode_default")]
+    pub batch_mode: String,
+    /// Table layout: `columns`, `jsonb` or `blob`.
+    #[serde(default = "postgres_layout_default")]
+    pub layout: String,
+    #[serde(default)]
+    pub unlogged: bool,
+    pub fillfactor: Option<u8>,
+    /// libpq's `disable`, `prefer`, `require`, `verify-ca` or `verify-full`.
+    /// Anything but `disable` and `prefer` needs the `tls` feature.
+    pub sslmode: Option<String>,
+    /// PEM file with the CA certificate to verify the server with.
+    pub sslrootcert: Option<String>,
+    /// PEM files with the client certificate and its PKCS#8 key.
+    pub sslcert: Option<String>,
+    pub sslkey: Option<String>,
+}
+
+impl Default for PostgresProperties {
+    fn default() -> Self {
+        PostgresProperties {
+            url: None,
+            host: None,
+            port: None,
+            user: None,
+            password: None,
+            dbname: None,
+            target_session_attrs: None,
+            connect_timeout: postgres_connect_timeout_default(),
+            pool_size: postgres_pool_size_default(),
+            statement_mode: postgres_statement_mode_default(),
+            batch_mode: postgres_batch_mode_default(),
+            layout: postgres_layout_default(),
+            unlogged: false,
+            fillfactor: None,
+            sslmode: None,
+            sslrootcert: None,
+            sslcert: None,
+            sslkey: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct Properties {
+    /// `core` for YCSB's synthetic mix, or `trace` for `run` to issue the
+    /// operations of `tracefile`.
+    #[serde(default = "workload_default")]
+    pub workload: String,
+    #[serde(default = "table_default")]
+    pub table: String,
     #[serde(default = "zero_u64", rename = "insertstart")]
     pub insert_start: u64,
     #[serde(default = "zero_u64", rename = "insertcount")]
     pub insert_count: u64,
+    /// Times `load` starts an insert over once the retry policy has given up
+    /// on it, before counting its records as lost.
+    #[serde(default = "zero_u64", rename = "core_workload_insertion_retry_limit")]
+    pub insertion_retry_limit: u64,
+    /// Seconds to wait before starting an insert over, give or take 20%.
+    #[serde(
+        default = "insertion_retry_interval_default",
+        rename = "core_workload_insertion_retry_interval"
+    )]
+    pub insertion_retry_interval: u64,
+    /// `hashed` spreads the keys of consecutive records over the keyspace,
+    /// `ordered` keeps them in record order.
+    #[serde(default = "insert_order_default", rename = "insertorder")]
+    pub insert_order: String,
+    /// Digits record numbers are zero-padded to in keys.
+    #[serde(default = "zero_padding_default", rename = "zeropadding")]
+    pub zero_padding: usize,
+    #[serde(default = "key_prefix_default", rename = "keyprefix")]
+    pub key_prefix: String,
     #[serde(rename = "operationcount")]
     pub operation_count: u64,
     #[serde(default = "zero_u64", rename = "recordcount")]
@@ -62,6 +252,17 @@ pub struct Properties {
         rename = "fieldlengthdistribution"
     )]
     pub field_length_distribution: String,
+    /// The histogram file `fieldlengthdistribution = "histogram"` draws
+    /// from.
+    #[serde(default = "field_length_histogram_default", rename = "fieldlengthhistogram")]
+    pub field_length_histogram: String,
+    /// What field values look like: `alphanumeric`, `random`,
+    /// `compressible`, `text` or `json`.
+    #[serde(default = "value_generator_default", rename = "valuegenerator")]
+    pub value_generator: String,
+    /// How much `compressible` values compress.
+    #[serde(default = "value_compression_ratio_default", rename = "valuecompressionratio")]
+    pub value_compression_ratio: f64,
     #[serde(
         default = "request_distribution_default",
         rename = "requestdistribution"
@@ -84,4 +285,111 @@ pub struct Properties {
         rename = "readmodifywriteproportion"
     )]
     pub read_modify_write_proportion: f64,
+    #[serde(default = "indexed_read_proportion_default", rename = "indexedreadproportion")]
+    pub indexed_read_proportion: f64,
+
+    #[serde(default = "max_scan_length_default", rename = "maxscanlength")]
+    pub max_scan_length: u64,
+    #[serde(
+        default = "scan_length_distribution_default",
+        rename = "scanlengthdistribution"
+    )]
+    pub scan_length_distribution: String,
+
+    /// Fields that get a secondary index when the table is created, and that
+    /// indexed reads query by.
+    #[serde(default, rename = "indexedfields")]
+    pub indexed_fields: Vec<String>,
+
+    /// Number of operations grouped into one transaction during `run`;
+    /// 0 runs every operation on its own.
+    #[serde(default = "zero_u64", rename = "opspertransaction", alias = "opsPerTransaction")]
+    pub ops_per_transaction: u64,
+    /// `read committed`, `repeatable read` or `serializable`; unset uses the
+    /// database's default.
+    #[serde(rename = "isolationlevel")]
+    pub isolation_level: Option<String>,
+
+    /// `none`, `fixed`, `exponential` or `amplify`; `--retries` caps the
+    /// attempts per operation.
+    #[serde(default = "retry_policy_default", rename = "retrypolicy")]
+    pub retry_policy: String,
+    /// Base delay of the `exponential` policy, in ms.
+    #[serde(default = "retry_backoff_default", rename = "retrybackoff")]
+    pub retry_backoff: u64,
+    /// Longest delay of the `exponential` policy, in ms.
+    #[serde(default = "retry_max_backoff_default", rename = "retrymaxbackoff")]
+    pub retry_max_backoff: u64,
+    /// Retries allowed per operation issued, e.g. 0.1 for one in ten; unset
+    /// doesn't limit them.
+    #[serde(rename = "retrybudget")]
+    pub retry_budget: Option<f64>,
+
+    /// Seeds the random choices of every thread, so that runs with the same
+    /// seed and thread count issue the same requests; unset seeds from the
+    /// OS.
+    pub seed: Option<u64>,
+    /// `run` inserts take every `run_insert_stride`-th key past `recordcount`
+    /// from `run_insert_offset` on, so that distributed workers insert
+    /// disjoint keys. Set by the coordinator, not the workload file.
+    #[serde(skip)]
+    pub run_insert_offset: u64,
+    #[serde(skip, default = "one_u64")]
+    pub run_insert_stride: u64,
+
+    /// Key access log the `trace` workload issues, as CSV (`.csv`) or JSONL
+    /// records of op, key, size and optionally time in ms.
+    #[serde(rename = "tracefile")]
+    pub trace_file: Option<String>,
+    /// Start over at the end of the trace until `operationcount` operations
+    /// are done.
+    #[serde(default, rename = "traceloop")]
+    pub trace_loop: bool,
+    /// How many times faster than logged to issue the trace's operations;
+    /// 0 issues them as fast as possible.
+    #[serde(default = "trace_rate_default", rename = "tracerate")]
+    pub trace_rate: f64,
+    /// Map the trace's keys onto the `recordcount` loaded records instead of
+    /// using them as they are.
+    #[serde(default, rename = "traceremapkeys")]
+    pub trace_remap_keys: bool,
+
+    /// Number of records inserted per database call during `load`.
+    #[serde(default = "batch_size_default", rename = "batchsize")]
+    pub batch_size: u64,
+
+    #[serde(default)]
+    pub postgres: PostgresProperties,
+}
+
+impl Properties {
+    /// Number of records `load` inserts: `insertcount` if set, otherwise the
+    /// records from `insertstart` up to `recordcount`.
+    pub fn load_count(&self) -> u64 {
+        if self.insert_count > 0 {
+            self.insert_count
+        } else {
+            self.record_count.saturating_sub(self.insert_start)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shipped_workloads_parse() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/workloads");
+        let mut parsed = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let raw = std::fs::read_to_string(&path).unwrap();
+            if let Err(err) = toml::from_str::<Properties>(&raw) {
+                panic!("{}: {}", path.display(), err);
+            }
+            parsed += 1;
+        }
+        assert!(parsed > 1);
+    }
 }
@@ -0,0 +1,265 @@
+use crate::db::ErrorKind;
+use crate::{Request, RequestKind};
+use hdrhistogram::Histogram;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::thread;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+fn new_histogram() -> Histogram<u64> {
+    Histogram::<u64>::new_with_bounds(1, 60 * 60 * 1000, 2).unwrap()
+}
+
+/// Latency / failure stats over some span of requests. Serializable so that
+/// workers can send theirs to the coordinator.
+#[derive(Serialize, Deserialize)]
+pub struct Summary {
+    /// Operations that finished, and those of them that failed after any
+    /// retries.
+    pub operations: u64,
+    pub failures: u64,
+    /// Every attempt at an operation, the ones that failed, and the ones that
+    /// were retries.
+    pub attempts: u64,
+    pub failed_attempts: u64,
+    pub retries: u64,
+    pub commits: u64,
+    pub failed_commits: u64,
+    pub aborts: u64,
+    pub serialization_failures: u64,
+    /// Records `load` couldn't insert even after starting over.
+    pub lost_inserts: u64,
+    /// Failed requests by the kind of error they failed with.
+    pub errors: BTreeMap<ErrorKind, u64>,
+    /// Latencies of successful operations, in ms.
+    #[serde(with = "recorded")]
+    pub histogram: Histogram<u64>,
+    /// Latencies of successful commits, in ms.
+    #[serde(with = "recorded")]
+    pub commit_histogram: Histogram<u64>,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Summary {
+            operations: 0,
+            failures: 0,
+            attempts: 0,
+            failed_attempts: 0,
+            retries: 0,
+            commits: 0,
+            failed_commits: 0,
+            aborts: 0,
+            serialization_failures: 0,
+            lost_inserts: 0,
+            errors: BTreeMap::new(),
+            histogram: new_histogram(),
+            commit_histogram: new_histogram(),
+        }
+    }
+}
+
+impl Summary {
+    pub fn record(&mut self, msg: &Request) {
+        // Counted once per transaction attempt, on its outcome.
+        if msg.serialization_failure && matches!(msg.kind, RequestKind::Commit | RequestKind::Abort) {
+            self.serialization_failures += 1;
+        }
+        if let Some(kind) = msg.error {
+            *self.errors.entry(kind).or_default() += 1;
+        }
+        match msg.kind {
+            RequestKind::Commit => {
+                self.commits += 1;
+                if msg.success {
+                    self.commit_histogram.record(msg.latency as u64).unwrap();
+                } else {
+                    self.failed_commits += 1;
+                }
+            }
+            RequestKind::Abort => self.aborts += 1,
+            RequestKind::Operation => {
+                if !msg.success {
+                    self.failed_attempts += 1;
+                } else {
+                    // all failure latency == timeout so don't bother tracking those
+                    self.histogram.record(msg.latency as u64).unwrap();
+                }
+                if msg.is_retry {
+                    self.retries += 1;
+                }
+                self.attempts += 1;
+            }
+            RequestKind::Outcome => {
+                if !msg.success {
+                    self.failures += 1;
+                }
+                self.operations += 1;
+            }
+            RequestKind::LostInsert { rows } => self.lost_inserts += rows,
+        }
+    }
+
+    pub fn merge(&mut self, other: &Summary) {
+        self.operations += other.operations;
+        self.failures += other.failures;
+        self.attempts += other.attempts;
+        self.failed_attempts += other.failed_attempts;
+        self.retries += other.retries;
+        self.commits += other.commits;
+        self.failed_commits += other.failed_commits;
+        self.aborts += other.aborts;
+        self.serialization_failures += other.serialization_failures;
+        self.lost_inserts += other.lost_inserts;
+        for (kind, count) in &other.errors {
+            *self.errors.entry(*kind).or_default() += count;
+        }
+        self.histogram.add(&other.histogram).unwrap();
+        self.commit_histogram.add(&other.commit_histogram).unwrap();
+    }
+
+    fn transactions(&self) -> u64 {
+        self.commits + self.aborts
+    }
+
+    /// The progress lines printed about every second.
+    fn print_interval(&self) {
+        if self.attempts > 0 {
+            println!("Success rate: {}, total reqs: {}, attempts: {}, retry frac: {}",
+                (self.operations - self.failures) as f64 / self.operations.max(1) as f64,
+                self.operations,
+                self.attempts,
+                self.retries as f64 / self.attempts as f64);
+            println!("P25: {}", self.histogram.value_at_quantile(0.25));
+            println!("P50: {}", self.histogram.value_at_quantile(0.50));
+            println!("P75: {}", self.histogram.value_at_quantile(0.75));
+            println!("P99: {}", self.histogram.value_at_quantile(0.99));
+        }
+        let transactions = self.transactions();
+        if transactions > 0 {
+            println!("Transactions: {}, commit success rate: {}, serialization failure rate: {}",
+                transactions,
+                (self.commits - self.failed_commits) as f64 / transactions as f64,
+                self.serialization_failures as f64 / transactions as f64);
+            println!("Commit P50: {}", self.commit_histogram.value_at_quantile(0.50));
+            println!("Commit P99: {}", self.commit_histogram.value_at_quantile(0.99));
+        }
+        if !self.errors.is_empty() {
+            let errors: Vec<String> = self.errors.iter().map(|(kind, count)| format!("{} {}", kind, count)).collect();
+            println!("Errors: {}", errors.join(", "));
+        }
+    }
+
+    /// The `[OVERALL]` lines for a whole phase.
+    pub fn print_overall(&self) {
+        println!("[OVERALL], Failures, {}", self.failures);
+        println!("[OVERALL], Attempts, {}", self.attempts);
+        println!("[OVERALL], FailedAttempts, {}", self.failed_attempts);
+        println!("[OVERALL], Retries, {}", self.retries);
+        println!("[OVERALL], 50thPercentileLatency(ms), {}", self.histogram.value_at_quantile(0.50));
+        println!("[OVERALL], 99thPercentileLatency(ms), {}", self.histogram.value_at_quantile(0.99));
+        if self.transactions() > 0 {
+            println!("[OVERALL], Transactions, {}", self.transactions());
+            println!("[OVERALL], Commits, {}", self.commits - self.failed_commits);
+            println!("[OVERALL], SerializationFailures, {}", self.serialization_failures);
+            println!("[OVERALL], Commit99thPercentileLatency(ms), {}", self.commit_histogram.value_at_quantile(0.99));
+        }
+        for (kind, count) in &self.errors {
+            println!("[ERRORS], {}, {}", kind, count);
+        }
+    }
+}
+
+/// Spawns the thread that tracks latency / failure stats. It prints them about
+/// every second, and returns the stats of the whole phase once all senders
+/// are gone.
+pub fn spawn(mut rx: UnboundedReceiver<Request>) -> thread::JoinHandle<Summary> {
+    thread::spawn(move || {
+        let waker = futures::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let mut summary = Summary::default();
+        let mut interval = Summary::default();
+        let mut now = std::time::Instant::now();
+        loop {
+            let closed = match rx.poll_recv(&mut cx) {
+                core::task::Poll::Ready(Some(msg)) => {
+                    //dbg!(&msg);
+                    summary.record(&msg);
+                    interval.record(&msg);
+                    false
+                }
+                core::task::Poll::Ready(None) => true,
+                core::task::Poll::Pending => false,
+            };
+
+            // Emit percentiles every ~ X ms
+            let ellapsed = now.elapsed().as_millis();
+            if ellapsed < 1000 && !closed {
+                continue;
+            }
+            // Reset the timer and emit percentiles
+            now = std::time::Instant::now();
+            interval.print_interval();
+            interval = Summary::default();
+
+            if closed {
+                return summary;
+            }
+        }
+    })
+}
+
+/// Serializes a histogram as its recorded `(value, count)` pairs.
+mod recorded {
+    use hdrhistogram::Histogram;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(histogram: &Histogram<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        let counts: Vec<(u64, u64)> = histogram
+            .iter_recorded()
+            .map(|v| (v.value_iterated_to(), v.count_at_value()))
+            .collect();
+        counts.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Histogram<u64>, D::Error> {
+        let mut histogram = super::new_histogram();
+        for (value, count) in Vec::<(u64, u64)>::deserialize(deserializer)? {
+            histogram.record_n(value, count).map_err(serde::de::Error::custom)?;
+        }
+        Ok(histogram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_round_trip() {
+        let mut summary = Summary::default();
+        for latency in 1..=100 {
+            summary.record(&Request {
+                latency,
+                success: true,
+                is_retry: false,
+                kind: RequestKind::Operation,
+                serialization_failure: false,
+                error: (latency % 10 == 0).then_some(ErrorKind::Timeout),
+            });
+        }
+        let sent: Summary = serde_json::from_str(&serde_json::to_string(&summary).unwrap()).unwrap();
+
+        let mut merged = Summary::default();
+        merged.merge(&sent);
+        merged.merge(&summary);
+        assert_eq!(merged.attempts, 200);
+        assert_eq!(merged.errors[&ErrorKind::Timeout], 20);
+        assert_eq!(merged.histogram.len(), 200);
+        assert_eq!(
+            merged.histogram.value_at_quantile(0.5),
+            summary.histogram.value_at_quantile(0.5)
+        );
+    }
+}
@@ -0,0 +1,32 @@
+//! Fixtures the unit tests share.
+
+use crate::properties::Properties;
+use crate::workload::CoreWorkload;
+use crate::Opt;
+use std::sync::Arc;
+use structopt::StructOpt;
+
+/// Parses `props`, which needs at least an `operationcount`.
+pub fn props(props: &str) -> Properties {
+    toml::from_str(props).unwrap()
+}
+
+/// Parses `props`, with Postgres on a port nothing listens on and no retries,
+/// so that every call fails quickly and no server is needed.
+pub fn unreachable_props(props: &str) -> Properties {
+    self::props(&format!(
+        "{}\nretrypolicy = \"none\"\n[postgres]\nhost = \"127.0.0.1\"\nport = 1\nconnect_timeout = 1",
+        props
+    ))
+}
+
+/// The options of `ycsb -d postgres -w test.toml`, followed by `args`.
+pub fn opt(args: &[&str]) -> Opt {
+    Opt::from_iter(["ycsb", "-d", "postgres", "-w", "test.toml"].iter().chain(args))
+}
+
+/// A core workload with the default options, whose stats go nowhere.
+pub fn core_workload(props: &Properties) -> anyhow::Result<CoreWorkload> {
+    let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+    CoreWorkload::new(props, &opt(&[]), Arc::new(tx))
+}
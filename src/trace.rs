@@ -0,0 +1,183 @@
+//! Operation traces: `run --record` writes every operation it issues to a
+//! JSONL file, and `replay --trace` issues them again, on the recorded
+//! schedule or as fast as it can.
+//!
+//! Values aren't recorded, only their sizes; replay fills them with fresh
+//! random data.
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::{mpsc, OnceLock};
+use std::thread;
+use std::time::Instant;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TracedOperation {
+    Read { key: String },
+    /// `sizes` maps every field written to the length of its value.
+    Update { key: String, sizes: BTreeMap<String, usize> },
+    Insert { key: String, sizes: BTreeMap<String, usize> },
+    ReadModifyWrite { key: String, sizes: BTreeMap<String, usize> },
+    Scan { key: String, count: usize },
+    ReadByField { field: String, value: String },
+    /// Operations run inside one transaction.
+    Transaction { ops: Vec<TracedOperation> },
+}
+
+impl TracedOperation {
+    /// How many operations this counts as in the stats.
+    pub fn operations(&self) -> usize {
+        match self {
+            TracedOperation::Transaction { ops } => ops.len(),
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    /// When the operation was issued, in µs since the phase started.
+    pub offset_us: u64,
+    #[serde(flatten)]
+    pub op: TracedOperation,
+}
+
+/// Writes records to a trace file from a thread of its own, so that
+/// recording doesn't make tasks wait on each other or on the disk.
+pub struct Recorder {
+    start: OnceLock<Instant>,
+    tx: Option<mpsc::Sender<TraceRecord>>,
+    writer: Option<thread::JoinHandle<Result<()>>>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let (tx, rx) = mpsc::channel::<TraceRecord>();
+        let writer = thread::spawn(move || {
+            for record in rx {
+                serde_json::to_writer(&mut file, &record)?;
+                file.write_all(b"\n")?;
+            }
+            file.flush()?;
+            Ok(())
+        });
+        Ok(Recorder {
+            start: OnceLock::new(),
+            tx: Some(tx),
+            writer: Some(writer),
+        })
+    }
+
+    /// Starts the clock the offsets count from, when the phase starts rather
+    /// than when it was set up. Otherwise it starts at the first record.
+    pub fn start(&self) {
+        self.start.get_or_init(Instant::now);
+    }
+
+    pub fn record(&self, op: TracedOperation) {
+        let offset_us = self.start.get_or_init(Instant::now).elapsed().as_micros() as u64;
+        if let Some(tx) = &self.tx {
+            // Only fails if the writer gave up, which `drop` reports.
+            let _ = tx.send(TraceRecord { offset_us, op });
+        }
+    }
+}
+
+impl Drop for Recorder {
+    /// Waits for the writer to flush everything recorded.
+    fn drop(&mut self) {
+        drop(self.tx.take());
+        if let Some(writer) = self.writer.take() {
+            if let Ok(Err(err)) = writer.join() {
+                eprintln!("failed to write trace: {}", err);
+            }
+        }
+    }
+}
+
+/// Reads a whole trace, in the order it was recorded. Transactions can't be
+/// nested, since they're replayed as one database transaction.
+pub fn read(path: &str) -> Result<Vec<TraceRecord>> {
+    let file = BufReader::new(File::open(path)?);
+    let mut records = vec![];
+    for (idx, line) in file.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: TraceRecord = serde_json::from_str(&line).map_err(|err| anyhow!("{}:{}: {}", path, idx + 1, err))?;
+        if let TracedOperation::Transaction { ops } = &record.op {
+            if ops.iter().any(|op| matches!(op, TracedOperation::Transaction { .. })) {
+                bail!("{}:{}: transactions can't be nested", path, idx + 1);
+            }
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_read() {
+        let path = std::env::temp_dir().join(format!("ycsb-trace-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let ops = vec![
+            TracedOperation::Read { key: "1".to_string() },
+            TracedOperation::Transaction {
+                ops: vec![
+                    TracedOperation::Update {
+                        key: "2".to_string(),
+                        sizes: [("field0".to_string(), 100)].into_iter().collect(),
+                    },
+                    TracedOperation::Scan { key: "3".to_string(), count: 10 },
+                ],
+            },
+        ];
+        let recorder = Recorder::create(path).unwrap();
+        for op in &ops {
+            recorder.record(op.clone());
+        }
+        drop(recorder);
+
+        let records = read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(records.iter().map(|r| r.op.clone()).collect::<Vec<_>>(), ops);
+        assert!(records[0].offset_us <= records[1].offset_us);
+    }
+
+    /// A distributed worker sets up its phase well before it starts, and the
+    /// wait mustn't count.
+    #[test]
+    fn test_offsets_count_from_start() {
+        let path = std::env::temp_dir().join(format!("ycsb-trace-start-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let recorder = Recorder::create(path).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        recorder.start();
+        recorder.record(TracedOperation::Read { key: "1".to_string() });
+        drop(recorder);
+
+        let records = read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(records[0].offset_us < 200_000, "{}", records[0].offset_us);
+    }
+
+    #[test]
+    fn test_nested_transaction_is_an_error() {
+        let path = std::env::temp_dir().join(format!("ycsb-nested-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let nested = r#"{"offset_us":1,"op":"transaction","ops":[{"op":"transaction","ops":[]}]}"#;
+        std::fs::write(path, format!("{{\"offset_us\":0,\"op\":\"read\",\"key\":\"1\"}}\n{}\n", nested)).unwrap();
+        let err = read(path).unwrap_err();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(err.to_string(), format!("{}:2: transactions can't be nested", path));
+    }
+}
@@ -1,18 +1,30 @@
 mod core_workload;
+mod retry;
+mod trace_workload;
 
 pub use core_workload::CoreWorkload;
+pub use trace_workload::TraceWorkload;
 
-use crate::db::DB;
-use std::rc::Rc;
-use std::cell::RefCell;
 use async_trait::async_trait;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 use crate::db::DBType;
+use rand::rngs::SmallRng;
 
+/// Shared by all tasks of a phase. Randomness comes from the calling task's
+/// `rng`, so the workload itself needs no locking.
 #[async_trait]
 pub trait Workload: Sync + Send {
-    async fn do_insert(&self, db: DBType);
-    async fn do_update(&self, db: DBType);
-    async fn do_transaction(&self, db: DBType);
+    /// Inserts record `keynum`.
+    async fn do_insert(&self, db: DBType, rng: &mut SmallRng, keynum: u64);
+    /// Inserts records `keynum..keynum + count` in one call.
+    async fn do_insert_batch(&self, db: DBType, rng: &mut SmallRng, keynum: u64, count: usize);
+    async fn do_update(&self, db: DBType, rng: &mut SmallRng);
+    async fn do_transaction(&self, db: DBType, rng: &mut SmallRng);
+    /// Runs `count` operations of the mix inside a single transaction.
+    async fn do_transactions_in_txn(&self, db: DBType, rng: &mut SmallRng, count: usize);
+
+    /// The number of operations `run` performs when asked for
+    /// `operation_count`, for workloads that can run out of them.
+    fn run_count(&self, operation_count: usize) -> usize {
+        operation_count
+    }
 }
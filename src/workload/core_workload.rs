@@ -1,22 +1,24 @@
 use crate::db::DB;
 use crate::workload::Workload;
-use rand::distributions::{Alphanumeric, DistString};
 use rand::rngs::SmallRng;
-use rand::SeedableRng;
+use rand::Rng;
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 //use std::cell::RefCell;
+use anyhow::bail;
 use async_trait::async_trait;
-use std::borrow::BorrowMut;
-use crate::db::DBType;
+use crate::db::{DBType, DbError, DbResult, ErrorKind, IsolationLevel};
+use crate::RequestKind;
+use std::future::Future;
+use std::sync::Arc;
 use tokio::time::{timeout, Duration};
 
 use crate::generator::{
-    AcknowledgedCounterGenerator, ConstantGenerator, CounterGenerator, DiscreteGenerator,
-    Generator, UniformLongGenerator, WeightPair, ZipfianGenerator,
+    AcknowledgedCounterGenerator, ConstantGenerator, DiscreteGenerator,
+    Generator, HistogramGenerator, UniformLongGenerator, ValueGenerator, WeightPair, ZipfianGenerator,
 };
 use crate::properties::Properties;
+use crate::trace::{Recorder, TracedOperation};
+use super::retry::{Attempt, RetryPolicy};
 
 #[derive(Copy, Clone, Debug)]
 pub enum CoreOperation {
@@ -25,6 +27,7 @@ pub enum CoreOperation {
     Insert,
     Scan,
     ReadModifyWrite,
+    IndexedRead,
 }
 
 impl std::fmt::Display for CoreOperation {
@@ -33,301 +36,575 @@ impl std::fmt::Display for CoreOperation {
     }
 }
 
+/// Shared by every task of a phase. The generators take the caller's rng, so
+/// tasks only contend on the counters and on zipfian parameters while the
+/// item count grows.
 #[allow(dead_code)]
 pub struct CoreWorkload {
-    rng: std::sync::Mutex<SmallRng>,
     table: String,
     field_count: u64,
     field_names: Vec<String>,
-    field_length_generator: std::sync::Mutex<Box<dyn Generator<u64> + Send>>,
+    field_length_generator: Box<dyn Generator<u64> + Send + Sync>,
+    value_generator: ValueGenerator,
     read_all_fields: bool,
     write_all_fields: bool,
     data_integrity: bool,
-    key_sequence: std::sync::Mutex<Box<dyn Generator<u64> + Send>>,
-    operation_chooser: std::sync::Mutex<DiscreteGenerator<CoreOperation>>,
-    key_chooser: std::sync::Mutex<Box<dyn Generator<u64> + Send>>,
+    operation_chooser: DiscreteGenerator<CoreOperation>,
+    key_chooser: Box<dyn Generator<u64> + Send + Sync>,
     //field_chooser: Box<dyn Generator<String>>,
-    transaction_insert_key_sequence: std::sync::Mutex<AcknowledgedCounterGenerator>,
-    //scan_length: Box<dyn Generator<u64>>,
+    transaction_insert_key_sequence: Arc<AcknowledgedCounterGenerator>,
+    scan_length: Box<dyn Generator<u64> + Send + Sync>,
+    indexed_fields: Vec<String>,
     ordered_inserts: bool,
     record_count: usize,
+    run_insert_offset: u64,
+    run_insert_stride: u64,
     zero_padding: usize,
+    key_prefix: String,
     insertion_retry_limit: u64,
     insertion_retry_interval: u64,
+    isolation_level: Option<IsolationLevel>,
     timeout: u64,
-    retries: u64,
+    retry: RetryPolicy,
+    recorder: Option<Recorder>,
     sender: crate::SenderType,
 }
 
 impl CoreWorkload {
-    pub fn new(prop: &Properties, opt: &crate::Opt, sender: crate::SenderType) -> Self {
-        let rng = SmallRng::from_entropy();
+    pub fn new(prop: &Properties, opt: &crate::Opt, sender: crate::SenderType) -> anyhow::Result<Self> {
+        // Inserts during `run` continue after the loaded records.
+        let transaction_insert_key_sequence = Arc::new(AcknowledgedCounterGenerator::new(prop.record_count.max(1)));
         let field_name_prefix = "field";
         let field_count = 10;
         let mut field_names = vec![];
         for i in 0..field_count {
             field_names.push(format!("{}{}", field_name_prefix, i));
         }
-        CoreWorkload {
-            rng: std::sync::Mutex::new(rng),
-            table: String::from("usertable"),
+        Ok(CoreWorkload {
+            table: prop.table.clone(),
             field_count,
             field_names,
-            field_length_generator: std::sync::Mutex::new(get_field_length_generator(prop)),
+            field_length_generator: get_field_length_generator(prop)?,
+            value_generator: ValueGenerator::new(&prop.value_generator, prop.value_compression_ratio)?,
             read_all_fields: true,
             write_all_fields: true,
             data_integrity: true,
-            key_sequence: std::sync::Mutex::new(Box::new(CounterGenerator::new(prop.insert_start))),
-            operation_chooser: std::sync::Mutex::new(create_operation_generator(prop)),
-            key_chooser: std::sync::Mutex::new(get_key_chooser_generator(prop)),
+            operation_chooser: create_operation_generator(prop)?,
+            key_chooser: get_key_chooser_generator(prop, &transaction_insert_key_sequence)?,
             //field_chooser: Box<dyn Generator<String>>,
-            transaction_insert_key_sequence: std::sync::Mutex::new(AcknowledgedCounterGenerator::new(1)),
-            //scan_length: Box<dyn Generator<u64>>,
-            ordered_inserts: true,
-            record_count: 1,
-            zero_padding: 1,
-            insertion_retry_limit: 0,
-            insertion_retry_interval: 0,
+            transaction_insert_key_sequence,
+            scan_length: get_scan_length_generator(prop)?,
+            indexed_fields: prop.indexed_fields.clone(),
+            ordered_inserts: match prop.insert_order.to_lowercase().as_str() {
+                "ordered" => true,
+                "hashed" => false,
+                order => bail!("unknown insertorder {}", order),
+            },
+            record_count: prop.record_count as usize,
+            run_insert_offset: prop.run_insert_offset,
+            run_insert_stride: prop.run_insert_stride,
+            zero_padding: prop.zero_padding,
+            key_prefix: prop.key_prefix.clone(),
+            insertion_retry_limit: prop.insertion_retry_limit,
+            insertion_retry_interval: prop.insertion_retry_interval,
+            isolation_level: prop
+                .isolation_level
+                .as_ref()
+                .map(|level| level.parse())
+                .transpose()?,
             timeout: opt.timeout, // ms
-            retries: opt.retries,
-            sender: sender,
+            retry: RetryPolicy::new(prop, opt.retries)?,
+            recorder: None,
+            sender,
+        })
+    }
+
+    /// Records the operations of the mix issued from now on to the trace
+    /// file at `path`.
+    pub fn record_to(&mut self, path: &str) -> anyhow::Result<()> {
+        self.recorder = Some(Recorder::create(path)?);
+        Ok(())
+    }
+
+    /// Starts the trace's clock, if recording, as the phase starts.
+    pub fn start_recording(&self) {
+        if let Some(recorder) = &self.recorder {
+            recorder.start();
         }
     }
 
-    async fn do_transaction_read(&self, mut db: DBType) {
-        let keynum = self.next_key_num();
-        let dbkey = format!("{}", fnvhash64(keynum));
-        let mut result = HashMap::new();
-        let mut retry = self.retries;
-        while retry > 0 {
-            let now = std::time::Instant::now();
-            let fut = db.read(&self.table, &dbkey, &mut result);
-            match timeout(Duration::from_millis(self.timeout), fut).await {
-                Err(_) => {
-                    //println!("read timeout 100ms");
-                    let is_retry = if retry != self.retries { true } else { false };
-                    self.sender.send(crate::Request { latency: now.elapsed().as_millis(), success: false, is_retry: is_retry }).unwrap();
-                    retry -= 1;
-                },
-                Ok(result) => {
-                    match result {
-                        Err(_) => {
-                            let is_retry = if retry != self.retries { true } else { false };
-                            self.sender.send(crate::Request { latency: now.elapsed().as_millis(), success: false, is_retry: is_retry }).unwrap();
-                            retry -= 1;
-                            continue;
-                        },
-                        _ => {},
-                    }
-                    // read succeeded
-                    //
-                    // If we previously failed, continue until retry == 0 to simulate workload amplification
-                    let is_retry = if retry != self.retries { true } else { false };
-                    self.sender.send(crate::Request { latency: now.elapsed().as_millis(), success: true, is_retry: is_retry }).unwrap();
-                    if retry != self.retries {
-                        retry -= 1;
-                        continue;
-                    }
-                    break;
-                },
+    /// Issues a traced operation, with fresh values of the recorded sizes.
+    pub async fn replay(&self, db: DBType, rng: &mut SmallRng, op: &TracedOperation) {
+        match op {
+            TracedOperation::Transaction { ops } => {
+                let ops = ops.iter().map(|op| self.traced_operation(op, rng)).collect();
+                self.execute_in_transaction(db, ops).await;
             }
+            op => self.execute(db, self.traced_operation(op, rng)).await,
         }
+    }
 
-        // TODO: verify rows
+    /// The call for a traced operation, with fresh values of the recorded
+    /// sizes.
+    fn traced_operation<'a>(&self, op: &'a TracedOperation, rng: &mut SmallRng) -> DbOperation<'a> {
+        let mut values = |sizes: &'a std::collections::BTreeMap<String, usize>| {
+            sizes
+                .iter()
+                .map(|(field, size)| (&field[..], self.value_generator.next_value(rng, *size)))
+                .collect()
+        };
+        match op {
+            TracedOperation::Read { key } => DbOperation::Read { key: key.clone() },
+            TracedOperation::Update { key, sizes } => DbOperation::Update { key: key.clone(), values: values(sizes) },
+            TracedOperation::Insert { key, sizes } => DbOperation::Insert {
+                key: key.clone(),
+                values: values(sizes),
+                keynum: None,
+            },
+            TracedOperation::ReadModifyWrite { key, sizes } => DbOperation::ReadModifyWrite {
+                key: key.clone(),
+                values: values(sizes),
+            },
+            TracedOperation::Scan { key, count } => DbOperation::Scan { start_key: key.clone(), count: *count },
+            TracedOperation::ReadByField { field, value } => DbOperation::ReadByField { field, value: value.clone() },
+            TracedOperation::Transaction { .. } => unreachable!("trace::read rejects nested transactions"),
+        }
     }
 
-    fn next_key_num(&self) -> u64 {
+    /// Picks the next operation of the mix and builds the call for it.
+    fn next_operation(&self, rng: &mut SmallRng) -> DbOperation<'_> {
+        match self.operation_chooser.next_value(rng) {
+            CoreOperation::Read => {
+                let keynum = self.next_key_num(rng);
+                let dbkey = self.build_key_name(keynum);
+                // TODO: verify rows
+                DbOperation::Read { key: dbkey }
+            }
+            CoreOperation::Update => DbOperation::Update {
+                key: self.build_key_name(self.next_key_num(rng)),
+                values: self.build_values(rng),
+            },
+            CoreOperation::Insert => {
+                let keynum = self.transaction_insert_key_sequence.next_value(rng);
+                DbOperation::Insert {
+                    key: self.build_key_name(self.record_num(keynum)),
+                    values: self.build_values(rng),
+                    keynum: Some(keynum),
+                }
+            }
+            CoreOperation::Scan => {
+                let keynum = self.next_key_num(rng);
+                let count = self.scan_length.next_value(rng);
+                DbOperation::Scan {
+                    start_key: self.build_key_name(keynum),
+                    count: count as usize,
+                }
+            }
+            CoreOperation::IndexedRead => {
+                let field = &self.indexed_fields[rng.gen_range(0..self.indexed_fields.len())];
+                // Start from a value like the stored ones.
+                let len = self.field_length_generator.next_value(rng) as usize;
+                let value = self.value_generator.next_value(rng, len);
+                DbOperation::ReadByField { field, value }
+            }
+            CoreOperation::ReadModifyWrite => DbOperation::ReadModifyWrite {
+                key: self.build_key_name(self.next_key_num(rng)),
+                values: self.build_values(rng),
+            },
+        }
+    }
+
+    /// A key that's been written: run-phase inserts only count once they've
+    /// been acknowledged.
+    fn next_key_num(&self, rng: &mut SmallRng) -> u64 {
         // FIXME: Handle case where keychooser is an ExponentialGenerator.
-        // FIXME: Handle case where keynum is > transactioninsertkeysequence's last value
-        self.key_chooser
-            .lock()
-            .unwrap()
-            .next_value(&mut self.rng.lock().unwrap())
+        let last = self.transaction_insert_key_sequence.last_value();
+        loop {
+            let keynum = self.key_chooser.next_value(rng);
+            if keynum <= last {
+                return self.record_num(keynum);
+            }
+        }
     }
-}
 
+    /// The record behind `keynum` of the insert sequence. Past the loaded
+    /// records, this process only gets every `run_insert_stride`-th one, so
+    /// it only reads the run-phase inserts it made itself.
+    fn record_num(&self, keynum: u64) -> u64 {
+        match keynum.checked_sub(self.record_count as u64) {
+            Some(past) => self.record_count as u64 + past * self.run_insert_stride + self.run_insert_offset,
+            None => keynum,
+        }
+    }
 
-#[async_trait]
-impl Workload for CoreWorkload {
-    async fn do_insert(&self, mut db: DBType) {
-        let dbkey = self
-            .key_sequence
-            .lock()
-            .unwrap()
-            .next_value(&mut self.rng.lock().unwrap());
-        let dbkey = format!("{}", fnvhash64(dbkey));
+    /// The key of record `keynum`, as upstream YCSB builds it.
+    pub fn build_key_name(&self, keynum: u64) -> String {
+        let value = if self.ordered_inserts {
+            keynum.to_string()
+        } else {
+            fnvhash64(keynum).to_string()
+        };
+        format!("{}{:0>width$}", self.key_prefix, value, width = self.zero_padding)
+    }
+
+    pub fn field_names(&self) -> &[String] {
+        &self.field_names
+    }
+
+    pub fn record_count(&self) -> usize {
+        self.record_count
+    }
+
+    /// The field values of a new record, drawn from `rng`.
+    pub fn build_values(&self, rng: &mut SmallRng) -> HashMap<&str, String> {
         let mut values = HashMap::new();
         for field_name in &self.field_names {
-            let field_len = self
-                .field_length_generator
-                .lock()
-                .unwrap()
-                .next_value(&mut self.rng.lock().unwrap());
-            let s = Alphanumeric
-                .sample_string::<SmallRng>(&mut self.rng.lock().unwrap(), field_len as usize);
+            let field_len = self.field_length_generator.next_value(rng);
+            let s = self.value_generator.next_value(rng, field_len as usize);
             values.insert(&field_name[..], s);
         }
-        //db.insert(&self.table, &dbkey, &values).await.unwrap();
-
-        let mut retry = self.retries;
-        while retry > 0 {
-            let now = std::time::Instant::now();
-            let fut = db.insert(&self.table, &dbkey, &values);
-            match timeout(Duration::from_millis(self.timeout), fut).await {
-                Err(_) => {
-                    //println!("insert timeout 100ms");
-                    let is_retry = if retry != self.retries { true } else { false };
-                    self.sender.send(crate::Request { latency: now.elapsed().as_millis(), success: false, is_retry: is_retry }).unwrap();
-                    retry -= 1;
-                },
-                Ok(result) => {
-                    match result {
-                        Err(_) => {
-                            let is_retry = if retry != self.retries { true } else { false };
-                            self.sender.send(crate::Request { latency: now.elapsed().as_millis(), success: false, is_retry: is_retry }).unwrap();
-                            retry -= 1;
-                            continue;
-                        },
-                        _ => {},
-                    }
+        values
+    }
 
-                    // read succeeded
-                    //
-                    // If we previously failed, continue until retry == 0 to simulate workload amplification
-                    let is_retry = if retry != self.retries { true } else { false };
-                    self.sender.send(crate::Request { latency: now.elapsed().as_millis(), success: true, is_retry: is_retry }).unwrap();
-                    if retry != self.retries {
-                        retry -= 1;
-                        continue;
-                    }
-                    break;
-                },
+    async fn issue(&self, db: &mut DBType, op: &DbOperation<'_>) -> DbResult<()> {
+        match op {
+            DbOperation::Read { key } => {
+                let mut result = HashMap::new();
+                db.read(&self.table, key, &mut result).await
+            }
+            DbOperation::Update { key, values } => db.update(&self.table, key, values).await,
+            DbOperation::ReadModifyWrite { key, values } => {
+                let mut result = HashMap::new();
+                db.read(&self.table, key, &mut result).await?;
+                db.update(&self.table, key, values).await
+            }
+            DbOperation::Insert { key, values, .. } => db.insert(&self.table, key, values).await,
+            DbOperation::Scan { start_key, count } => {
+                let mut result = vec![];
+                db.scan(&self.table, start_key, *count, &mut result).await
+            }
+            DbOperation::ReadByField { field, value } => {
+                let mut result = HashMap::new();
+                db.read_by_field(&self.table, field, value, &mut result).await
             }
+            DbOperation::BatchInsert { records } => db.batch_insert(&self.table, records).await,
         }
     }
 
-    async fn do_update(&self, mut db: DBType) {
-        let dbkey = self
-            .key_sequence
-            .lock()
-            .unwrap()
-            .next_value(&mut self.rng.lock().unwrap());
-        let dbkey = format!("{}", fnvhash64(dbkey));
-        let mut values = HashMap::new();
-        for field_name in &self.field_names {
-            let field_len = self
-                .field_length_generator
-                .lock()
-                .unwrap()
-                .next_value(&mut self.rng.lock().unwrap());
-            let s = Alphanumeric
-                .sample_string::<SmallRng>(&mut self.rng.lock().unwrap(), field_len as usize);
-            values.insert(&field_name[..], s);
+    /// Runs `fut`, failing it with a timeout error after the configured
+    /// timeout.
+    async fn limited<F: Future<Output = DbResult<()>>>(&self, fut: F) -> DbResult<()> {
+        timeout(Duration::from_millis(self.timeout), fut)
+            .await
+            .unwrap_or_else(|_| Err(DbError::timeout()))
+    }
+
+    fn report(&self, kind: RequestKind, now: std::time::Instant, result: &DbResult<()>, is_retry: bool) {
+        let error = result.as_ref().err();
+        self.sender.send(crate::Request {
+            latency: now.elapsed().as_millis(),
+            success: error.is_none(),
+            is_retry,
+            kind,
+            serialization_failure: error.is_some_and(DbError::is_serialization_failure),
+            error: error.map(DbError::kind),
+        }).unwrap();
+    }
+
+    /// Reports the final result of `count` operations started at `now`.
+    fn report_outcome(&self, now: std::time::Instant, success: bool, count: usize) {
+        for _ in 0..count {
+            self.sender.send(crate::Request {
+                latency: now.elapsed().as_millis(),
+                success,
+                is_retry: false,
+                kind: RequestKind::Outcome,
+                serialization_failure: false,
+                error: None,
+            }).unwrap();
         }
-        //db.update(&self.table, &dbkey, &values).await.unwrap();
+    }
+
+    /// Issues `op`, retrying as the retry policy says, and reports every
+    /// attempt as well as the outcome to the stats thread.
+    async fn execute(&self, mut db: DBType, op: DbOperation<'_>) {
+        let start = std::time::Instant::now();
+        let succeeded = match &op {
+            // The read and the update are each retried on their own, and
+            // together make one operation.
+            DbOperation::ReadModifyWrite { key, values } => {
+                self.attempt(&mut db, &DbOperation::Read { key: key.clone() }).await
+                    && self.attempt(&mut db, &DbOperation::Update { key: key.clone(), values: values.clone() }).await
+            }
+            op => self.attempt(&mut db, op).await,
+        };
+        self.report_outcome(start, succeeded, 1);
+        self.acknowledge(&op);
+    }
+
+    /// Lets the key chooser pick the key of a run-phase insert once it's
+    /// done. Failed inserts are acknowledged too, as upstream does, since
+    /// the chooser could never move past a key that isn't.
+    fn acknowledge(&self, op: &DbOperation<'_>) {
+        if let DbOperation::Insert { keynum: Some(keynum), .. } = op {
+            self.transaction_insert_key_sequence.acknowledge(*keynum);
+        }
+    }
 
-        let mut retry = self.retries;
-        while retry > 0 {
+    /// Issues `op` until it succeeds or the retry policy gives up, reporting
+    /// every attempt, and returns whether it succeeded.
+    async fn attempt(&self, db: &mut DBType, op: &DbOperation<'_>) -> bool {
+        let mut retries = self.retry.start();
+        loop {
             let now = std::time::Instant::now();
-            let fut = db.update(&self.table, &dbkey, &values);
-            match timeout(Duration::from_millis(self.timeout), fut).await {
-                Err(_) => {
-                    //println!("update timeout 100ms");
-                    let is_retry = if retry != self.retries { true } else { false };
-                    self.sender.send(crate::Request { latency: now.elapsed().as_millis(), success: false, is_retry: is_retry }).unwrap();
-                    retry -= 1;
-                },
-                Ok(result) => {
-                    match result {
-                        Err(_) => {
-                            let is_retry = if retry != self.retries { true } else { false };
-                            self.sender.send(crate::Request { latency: now.elapsed().as_millis(), success: false, is_retry: is_retry }).unwrap();
-                            retry -= 1;
-                            continue;
-                        },
-                        _ => {},
-                    }
-                    // read succeeded
-                    let is_retry = if retry != self.retries { true } else { false };
-                    self.sender.send(crate::Request { latency: now.elapsed().as_millis(), success: true, is_retry: is_retry}).unwrap();
-                    if retry != self.retries {
-                        retry -= 1;
-                        continue;
-                    }
-                    break;
-                },
+            let fut = self.issue(db, op);
+            let is_retry = retries.is_retry();
+            let result = self.limited(fut).await;
+            self.report(RequestKind::Operation, now, &result, is_retry);
+            match retries.next(Attempt::from_result(&result)) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => break,
             }
         }
+        retries.succeeded()
     }
 
-    async fn do_transaction(&self, db: DBType) {
-        let op = self
-            .operation_chooser
-            .lock()
-            .unwrap()
-            .next_value(&mut self.rng.lock().unwrap());
-        //dbg!(&op);
-        match op {
-            CoreOperation::Read => {
-                self.do_transaction_read(db).await;
+    /// Issues a `load` insert of `rows` records like `execute`, with an
+    /// outcome for every record, but starts it
+    /// over up to `insertion_retry_limit` times, `insertion_retry_interval`
+    /// seconds apart, when the retry policy gives up on it. Reports the
+    /// records as lost if that doesn't help either.
+    async fn execute_insert(&self, mut db: DBType, op: DbOperation<'_>, rows: usize) {
+        let start = std::time::Instant::now();
+        let mut succeeded = self.attempt(&mut db, &op).await;
+        for _ in 0..self.insertion_retry_limit {
+            if succeeded {
+                break;
             }
-            CoreOperation::Update => {
-                self.do_update(db).await;
+            // Jittered as upstream does, so that inserts that failed together
+            // don't start over together.
+            let interval = self.insertion_retry_interval as f64 * rand::thread_rng().gen_range(0.8..1.2);
+            tokio::time::sleep(Duration::from_secs_f64(interval)).await;
+            succeeded = self.attempt(&mut db, &op).await;
+        }
+        self.report_outcome(start, succeeded, rows);
+        if !succeeded {
+            self.sender.send(crate::Request {
+                latency: start.elapsed().as_millis(),
+                success: false,
+                is_retry: false,
+                kind: RequestKind::LostInsert { rows: rows as u64 },
+                serialization_failure: false,
+                error: None,
+            }).unwrap();
+        }
+    }
+
+    /// Runs `ops` in one transaction, retrying the whole transaction as the
+    /// retry policy says. Operations inside it aren't retried on their own,
+    /// since the transaction is aborted by then anyway.
+    async fn execute_in_transaction(&self, mut db: DBType, ops: Vec<DbOperation<'_>>) {
+        let start = std::time::Instant::now();
+        let mut retries = self.retry.start();
+        loop {
+            let is_retry = retries.is_retry();
+            let attempt = self.try_transaction(&mut db, &ops, is_retry).await;
+            match retries.next(attempt) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => break,
             }
-            CoreOperation::Insert => {
-                self.do_insert(db).await;
+        }
+        self.report_outcome(start, retries.succeeded(), ops.len());
+        for op in &ops {
+            self.acknowledge(op);
+        }
+    }
+
+    async fn try_transaction(&self, db: &mut DBType, ops: &[DbOperation<'_>], is_retry: bool) -> Attempt {
+        let now = std::time::Instant::now();
+        let result = self.limited(db.begin(self.isolation_level)).await;
+        if result.is_err() {
+            self.report(RequestKind::Abort, now, &result, is_retry);
+            let _ = self.limited(db.rollback()).await;
+            return Attempt::from_result(&result);
+        }
+
+        for op in ops {
+            let now = std::time::Instant::now();
+            let result = self.limited(self.issue(db, op)).await;
+            self.report(RequestKind::Operation, now, &result, is_retry);
+            if let Err(err) = &result {
+                let now = std::time::Instant::now();
+                let _ = self.limited(db.rollback()).await;
+                // Attribute the abort to the failure that caused it, without
+                // counting its error a second time.
+                self.sender.send(crate::Request {
+                    latency: now.elapsed().as_millis(),
+                    success: false,
+                    is_retry,
+                    kind: RequestKind::Abort,
+                    serialization_failure: err.is_serialization_failure(),
+                    error: None,
+                }).unwrap();
+                return Attempt::from_result(&result);
             }
-            _ => todo!(),
         }
+
+        let now = std::time::Instant::now();
+        let result = self.limited(db.commit()).await;
+        self.report(RequestKind::Commit, now, &result, is_retry);
+        if matches!(&result, Err(err) if err.kind() == ErrorKind::Timeout) {
+            // Timed out, so the transaction may still be open.
+            let _ = self.limited(db.rollback()).await;
+        }
+        Attempt::from_result(&result)
     }
 }
 
-// http://en.wikipedia.org/wiki/Fowler_Noll_Vo_hash
-fn fnvhash64(val: u64) -> u64 {
-    let mut val = val;
-    let prime = 0xcbf29ce484222325;
-    let mut hashval = prime;
-    for _ in 0..8 {
-        let octet = val & 0x00ff;
-        val >>= 8;
-        hashval ^= octet;
-        hashval = hashval.wrapping_mul(prime);
-    }
-    hashval
+/// A single call against the database, built up front so that it can be
+/// retried as-is.
+#[derive(Debug, PartialEq)]
+enum DbOperation<'a> {
+    Read { key: String },
+    Update { key: String, values: HashMap<&'a str, String> },
+    /// A read of `key` followed by an update of it.
+    ReadModifyWrite { key: String, values: HashMap<&'a str, String> },
+    /// `keynum` is set for run-phase inserts, which are acknowledged once
+    /// done.
+    Insert { key: String, values: HashMap<&'a str, String>, keynum: Option<u64> },
+    Scan { start_key: String, count: usize },
+    ReadByField { field: &'a str, value: String },
+    BatchInsert { records: Vec<(String, HashMap<&'a str, String>)> },
 }
 
-fn get_field_length_generator(prop: &Properties) -> Box<dyn Generator<u64> + Send> {
+impl DbOperation<'_> {
+    /// What a trace records about the operation: everything but the values
+    /// themselves.
+    fn trace(&self) -> TracedOperation {
+        let sizes = |values: &HashMap<&str, String>| values.iter().map(|(f, v)| (f.to_string(), v.len())).collect();
+        match self {
+            DbOperation::Read { key } => TracedOperation::Read { key: key.clone() },
+            DbOperation::Update { key, values } => TracedOperation::Update { key: key.clone(), sizes: sizes(values) },
+            DbOperation::ReadModifyWrite { key, values } => {
+                TracedOperation::ReadModifyWrite { key: key.clone(), sizes: sizes(values) }
+            }
+            DbOperation::Insert { key, values, .. } => TracedOperation::Insert { key: key.clone(), sizes: sizes(values) },
+            DbOperation::Scan { start_key, count } => TracedOperation::Scan { key: start_key.clone(), count: *count },
+            DbOperation::ReadByField { field, value } => TracedOperation::ReadByField {
+                field: field.to_string(),
+                value: value.clone(),
+            },
+            DbOperation::BatchInsert { .. } => unreachable!("only the operations of the mix are traced"),
+        }
+    }
+}
+
+#[async_trait]
+impl Workload for CoreWorkload {
+    async fn do_insert(&self, db: DBType, rng: &mut SmallRng, keynum: u64) {
+        let key = self.build_key_name(keynum);
+        let values = self.build_values(rng);
+        self.execute_insert(db, DbOperation::Insert { key, values, keynum: None }, 1).await;
+    }
+
+    async fn do_insert_batch(&self, db: DBType, rng: &mut SmallRng, keynum: u64, count: usize) {
+        let records = (keynum..keynum + count as u64)
+            .map(|keynum| (self.build_key_name(keynum), self.build_values(rng)))
+            .collect();
+        self.execute_insert(db, DbOperation::BatchInsert { records }, count).await;
+    }
+
+    async fn do_update(&self, db: DBType, rng: &mut SmallRng) {
+        let key = self.build_key_name(self.next_key_num(rng));
+        let values = self.build_values(rng);
+        self.execute(db, DbOperation::Update { key, values }).await;
+    }
+
+    async fn do_transaction(&self, db: DBType, rng: &mut SmallRng) {
+        let op = self.next_operation(rng);
+        if let Some(recorder) = &self.recorder {
+            recorder.record(op.trace());
+        }
+        self.execute(db, op).await;
+    }
+
+    async fn do_transactions_in_txn(&self, db: DBType, rng: &mut SmallRng, count: usize) {
+        let ops: Vec<_> = (0..count).map(|_| self.next_operation(rng)).collect();
+        if let Some(recorder) = &self.recorder {
+            let ops = ops.iter().map(DbOperation::trace).collect();
+            recorder.record(TracedOperation::Transaction { ops });
+        }
+        self.execute_in_transaction(db, ops).await;
+    }
+}
+
+// http://en.wikipedia.org/wiki/Fowler_Noll_Vo_hash, FNV-1a of `bytes`.
+pub fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 1099511628211;
+    bytes
+        .into_iter()
+        .fold(OFFSET_BASIS, |hashval, octet| (hashval ^ octet as u64).wrapping_mul(PRIME))
+}
+
+// FNV-1a of `val`'s octets, lowest first, made non-negative the way
+// upstream's `Utils.fnvhash64` does with `Math.abs`.
+fn fnvhash64(val: u64) -> i64 {
+    (fnv1a(val.to_le_bytes()) as i64).wrapping_abs()
+}
+
+fn get_field_length_generator(prop: &Properties) -> anyhow::Result<Box<dyn Generator<u64> + Send + Sync>> {
     match prop.field_length_distribution.to_lowercase().as_str() {
-        "constant" => Box::new(ConstantGenerator::new(prop.field_length)),
-        "uniform" => Box::new(UniformLongGenerator::new(1, prop.field_length)),
-        "zipfian" => Box::new(ZipfianGenerator::from_range(1, prop.field_length)),
-        "histogram" => unimplemented!(),
-        _ => panic!(
-            "unknown field length distribution {}",
-            prop.field_length_distribution
-        ),
+        "constant" => Ok(Box::new(ConstantGenerator::new(prop.field_length))),
+        "uniform" => Ok(Box::new(UniformLongGenerator::new(1, prop.field_length))),
+        "zipfian" => Ok(Box::new(ZipfianGenerator::from_range(1, prop.field_length))),
+        "histogram" => match HistogramGenerator::from_file(&prop.field_length_histogram) {
+            Ok(histogram) => Ok(Box::new(histogram)),
+            Err(err) => bail!("invalid fieldlengthhistogram: {}", err),
+        },
+        distribution => bail!("unknown fieldlengthdistribution {}", distribution),
+    }
+}
+
+/// Zipfian over every record whose insert has been acknowledged, so that
+/// records inserted during `run` become readable as they come in.
+struct GrowingZipfian {
+    zipfian: ZipfianGenerator,
+    basis: Arc<AcknowledgedCounterGenerator>,
+}
+
+impl Generator<u64> for GrowingZipfian {
+    fn next_value(&self, rng: &mut SmallRng) -> u64 {
+        self.zipfian.next_long(self.basis.last_value() + 1, rng)
     }
 }
 
-fn get_key_chooser_generator(prop: &Properties) -> Box<dyn Generator<u64> + Send> {
-    let insert_count = if prop.insert_count > 1 {
-        prop.insert_count
-    } else {
-        prop.record_count - prop.insert_start
-    };
-    assert!(insert_count > 1);
+/// Requests target every record in the table, not just the
+/// `insertstart`/`insertcount` slice this client loaded.
+fn get_key_chooser_generator(
+    prop: &Properties,
+    basis: &Arc<AcknowledgedCounterGenerator>,
+) -> anyhow::Result<Box<dyn Generator<u64> + Send + Sync>> {
+    let last_key = prop.record_count.max(1) - 1;
     match prop.request_distribution.to_lowercase().as_str() {
-        "uniform" => Box::new(UniformLongGenerator::new(
-            prop.insert_start,
-            prop.insert_start + insert_count - 1,
-        )),
-        "zipfian" => Box::new(ZipfianGenerator::from_range(prop.insert_start,
-                              prop.insert_start + insert_count - 1)),
-        _ => todo!(),
+        "uniform" => Ok(Box::new(UniformLongGenerator::new(0, last_key))),
+        "zipfian" => Ok(Box::new(GrowingZipfian {
+            zipfian: ZipfianGenerator::from_range(0, last_key),
+            basis: basis.clone(),
+        })),
+        distribution => bail!("unknown requestdistribution {}", distribution),
     }
 }
 
-fn create_operation_generator(prop: &Properties) -> DiscreteGenerator<CoreOperation> {
+fn get_scan_length_generator(prop: &Properties) -> anyhow::Result<Box<dyn Generator<u64> + Send + Sync>> {
+    match prop.scan_length_distribution.to_lowercase().as_str() {
+        "uniform" => Ok(Box::new(UniformLongGenerator::new(1, prop.max_scan_length))),
+        "zipfian" => Ok(Box::new(ZipfianGenerator::from_range(1, prop.max_scan_length))),
+        distribution => bail!("unknown scanlengthdistribution {}", distribution),
+    }
+}
+
+fn create_operation_generator(prop: &Properties) -> anyhow::Result<DiscreteGenerator<CoreOperation>> {
     let mut pairs = vec![];
     if prop.read_proportion > 0.0 {
         pairs.push(WeightPair::new(prop.read_proportion, CoreOperation::Read));
@@ -353,6 +630,200 @@ fn create_operation_generator(prop: &Properties) -> DiscreteGenerator<CoreOperat
             CoreOperation::ReadModifyWrite,
         ));
     }
+    if prop.indexed_read_proportion > 0.0 {
+        if prop.indexed_fields.is_empty() {
+            bail!("indexedreadproportion needs indexedfields");
+        }
+        pairs.push(WeightPair::new(
+            prop.indexed_read_proportion,
+            CoreOperation::IndexedRead,
+        ));
+    }
+
+    Ok(DiscreteGenerator::new(pairs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use crate::testing;
+    use std::sync::Arc;
+
+    fn try_workload(prop: &str) -> anyhow::Result<CoreWorkload> {
+        testing::core_workload(&testing::props(prop))
+    }
+
+    fn workload(prop: &str) -> CoreWorkload {
+        try_workload(prop).unwrap()
+    }
+
+    #[test]
+    fn test_same_seed_same_operations() {
+        let prop = "operationcount = 1\nrecordcount = 1000\nreadproportion = 0.5\nupdateproportion = 0.5";
+        fn operations(wl: &CoreWorkload, seed: u64) -> Vec<DbOperation<'_>> {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            (0..100).map(|_| wl.next_operation(&mut rng)).collect()
+        }
+        let (first, second, third) = (workload(prop), workload(prop), workload(prop));
+        assert_eq!(operations(&first, 42), operations(&second, 42));
+        assert_ne!(operations(&first, 42), operations(&third, 43));
+    }
+
+    /// Inserts into a port nothing listens on, so that every attempt fails.
+    #[tokio::test]
+    async fn test_insert_retries_and_lost_rows() {
+        let prop = testing::unreachable_props(
+            "operationcount = 1\nrecordcount = 10\n\
+             core_workload_insertion_retry_limit = 2\ncore_workload_insertion_retry_interval = 1",
+        );
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let wl = CoreWorkload::new(&prop, &testing::opt(&[]), Arc::new(tx)).unwrap();
+        let db = DBType::new(&prop.postgres).await.unwrap();
+
+        let start = std::time::Instant::now();
+        wl.do_insert_batch(db, &mut SmallRng::seed_from_u64(1), 0, 5).await;
+        // Two jittered intervals of 0.8 to 1.2 seconds.
+        assert!(start.elapsed() >= Duration::from_millis(1600));
+        drop(wl);
+
+        let mut summary = crate::stats::Summary::default();
+        while let Some(msg) = rx.recv().await {
+            summary.record(&msg);
+        }
+        assert_eq!((summary.attempts, summary.failed_attempts), (3, 3));
+        assert_eq!((summary.operations, summary.failures), (5, 5));
+        assert_eq!(summary.lost_inserts, 5);
+    }
+
+    #[test]
+    fn test_bad_properties_are_errors() {
+        assert!(try_workload("operationcount = 1").is_ok());
+        assert!(try_workload("operationcount = 1\nisolationlevel = \"serialisable\"").is_err());
+        assert!(try_workload("operationcount = 1\nretrypolicy = \"sometimes\"").is_err());
+        assert!(try_workload("operationcount = 1\nscanlengthdistribution = \"latest\"").is_err());
+        assert!(try_workload("operationcount = 1\nindexedreadproportion = 0.1").is_err());
+        assert!(try_workload("operationcount = 1\ninsertorder = \"sorted\"").is_err());
+        let histogram = "operationcount = 1\nfieldlengthdistribution = \"histogram\"\nfieldlengthhistogram = \"missing.txt\"";
+        assert!(try_workload(histogram).is_err());
+        assert!(try_workload("operationcount = 1\nvaluegenerator = \"words\"").is_err());
+    }
+
+    #[test]
+    fn test_key_names_match_upstream() {
+        let hashed = workload("operationcount = 1");
+        assert_eq!(hashed.build_key_name(0), "user6284781860667377211");
+        assert_eq!(hashed.build_key_name(42), "user55488592825689361");
+
+        let ordered = workload("operationcount = 1\ninsertorder = \"ordered\"\nzeropadding = 5\nkeyprefix = \"k\"");
+        assert_eq!(ordered.build_key_name(42), "k00042");
+        assert_eq!(ordered.build_key_name(123456), "k123456");
+    }
+
+    #[test]
+    fn test_read_modify_write() {
+        let wl = workload("operationcount = 1\nrecordcount = 10\nreadproportion = 0\nupdateproportion = 0\nreadmodifywriteproportion = 1");
+        let mut rng = SmallRng::seed_from_u64(1);
+        let written: Vec<_> = (0..10).map(|keynum| wl.build_key_name(keynum)).collect();
+        for _ in 0..100 {
+            match wl.next_operation(&mut rng) {
+                DbOperation::ReadModifyWrite { key, values } => {
+                    assert!(written.contains(&key));
+                    assert_eq!(values.len(), 10);
+                }
+                op => panic!("expected a read-modify-write, got {:?}", op),
+            }
+        }
+    }
+
+    #[test]
+    fn test_reads_wait_for_acknowledged_inserts() {
+        let wl = workload("operationcount = 1\nrecordcount = 10\nreadproportion = 0.5\ninsertproportion = 0.5");
+        let mut rng = SmallRng::seed_from_u64(1);
+        let ops: Vec<_> = (0..1000).map(|_| wl.next_operation(&mut rng)).collect();
+        let inserted: Vec<_> = ops
+            .iter()
+            .filter_map(|op| match op {
+                DbOperation::Insert { keynum: Some(keynum), .. } => Some(*keynum),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(inserted[..3], [10, 11, 12]);
+        let written: Vec<_> = (0..10).map(|keynum| wl.build_key_name(keynum)).collect();
+        for op in &ops {
+            if let DbOperation::Read { key } = op {
+                assert!(written.contains(key));
+            }
+        }
+
+        for op in &ops {
+            wl.acknowledge(op);
+        }
+        assert_eq!(wl.transaction_insert_key_sequence.last_value(), 9 + inserted.len() as u64);
+    }
 
-    DiscreteGenerator::new(pairs)
+    #[test]
+    fn test_striped_run_inserts() {
+        let prop = "operationcount = 1\nrecordcount = 10\nreadproportion = 0.5\ninsertproportion = 0.5";
+        let workers: Vec<_> = (0..2)
+            .map(|offset| {
+                let mut prop = testing::props(prop);
+                prop.run_insert_offset = offset;
+                prop.run_insert_stride = 2;
+                testing::core_workload(&prop).unwrap()
+            })
+            .collect();
+
+        let mut inserted = vec![vec![], vec![]];
+        for (wl, inserted) in workers.iter().zip(&mut inserted) {
+            let mut rng = SmallRng::seed_from_u64(1);
+            for _ in 0..1000 {
+                let op = wl.next_operation(&mut rng);
+                match &op {
+                    DbOperation::Insert { key, .. } => inserted.push(key.clone()),
+                    DbOperation::Read { key } => {
+                        let loaded = (0..10).any(|keynum| wl.build_key_name(keynum) == *key);
+                        assert!(loaded || inserted.contains(key));
+                    }
+                    _ => {}
+                }
+                wl.acknowledge(&op);
+            }
+        }
+        let (first, second) = (&workers[0], &workers[1]);
+        assert_eq!(inserted[0][..2], [first.build_key_name(10), first.build_key_name(12)]);
+        assert_eq!(inserted[1][..2], [second.build_key_name(11), second.build_key_name(13)]);
+        assert!(inserted[0].iter().all(|key| !inserted[1].contains(key)));
+    }
+
+    /// Client-side throughput of building operations, with no database in
+    /// the way. Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_next_operation() {
+        let wl = Arc::new(workload(
+            "operationcount = 1\nrecordcount = 100000\nreadproportion = 0.5\nupdateproportion = 0.5\nrequestdistribution = \"zipfian\"",
+        ));
+
+        const OPERATIONS: usize = 200_000;
+        for threads in [1, 2, 4, 8, 16] {
+            let start = std::time::Instant::now();
+            let handles: Vec<_> = (0..threads)
+                .map(|_| {
+                    let wl = wl.clone();
+                    std::thread::spawn(move || {
+                        let mut rng = SmallRng::from_entropy();
+                        for _ in 0..OPERATIONS / threads {
+                            std::hint::black_box(wl.next_operation(&mut rng));
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            let elapsed = start.elapsed().as_secs_f64();
+            println!("{} threads: {:.0} ops/sec", threads, OPERATIONS as f64 / elapsed);
+        }
+    }
 }
@@ -0,0 +1,233 @@
+use crate::db::DbResult;
+use crate::properties::Properties;
+use anyhow::{anyhow, Result};
+use rand::Rng;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::time::Duration;
+
+/// When a failed operation is tried again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PolicyKind {
+    /// Never.
+    None,
+    /// Right away, up to the attempt limit.
+    Fixed,
+    /// After a random delay of up to `retrybackoff * 2^n`, capped at
+    /// `retrymaxbackoff`.
+    Exponential,
+    /// Like `Fixed`, but once an operation has failed it keeps being issued
+    /// until the attempt limit, even after it succeeds, to simulate clients
+    /// that amplify load when the database struggles.
+    Amplify,
+}
+
+impl std::str::FromStr for PolicyKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(PolicyKind::None),
+            "fixed" => Ok(PolicyKind::Fixed),
+            "exponential" => Ok(PolicyKind::Exponential),
+            "amplify" => Ok(PolicyKind::Amplify),
+            _ => Err(anyhow!("unknown retry policy {}", s)),
+        }
+    }
+}
+
+/// Retries a budget allows without any operations to earn them, and the most
+/// it can save up.
+const BUDGET_RESERVE: u64 = 10 * BUDGET_UNIT;
+const BUDGET_CAP: u64 = 100 * BUDGET_UNIT;
+/// Budget is kept in thousandths of a retry.
+const BUDGET_UNIT: u64 = 1000;
+
+/// Limits retries to a share of the operations issued, so that a struggling
+/// database doesn't get buried under them.
+struct RetryBudget {
+    /// Thousandths of a retry earned by each operation.
+    per_operation: u64,
+    balance: AtomicU64,
+}
+
+impl RetryBudget {
+    fn deposit(&self) {
+        let _ = self.balance.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |balance| {
+            Some((balance + self.per_operation).min(BUDGET_CAP))
+        });
+    }
+
+    fn withdraw(&self) -> bool {
+        self.balance
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |balance| balance.checked_sub(BUDGET_UNIT))
+            .is_ok()
+    }
+}
+
+pub struct RetryPolicy {
+    kind: PolicyKind,
+    /// Attempts per operation, the first one included.
+    attempts: u64,
+    backoff: Duration,
+    max_backoff: Duration,
+    budget: Option<RetryBudget>,
+}
+
+impl RetryPolicy {
+    pub fn new(prop: &Properties, attempts: u64) -> Result<Self> {
+        let kind: PolicyKind = prop.retry_policy.parse()?;
+        let budget = match prop.retry_budget {
+            Some(ratio) if ratio < 0.0 => return Err(anyhow!("retrybudget must not be negative")),
+            Some(ratio) => Some(RetryBudget {
+                per_operation: (ratio * BUDGET_UNIT as f64) as u64,
+                balance: AtomicU64::new(BUDGET_RESERVE),
+            }),
+            None => None,
+        };
+        Ok(RetryPolicy {
+            kind,
+            attempts: if kind == PolicyKind::None { 1 } else { attempts.max(1) },
+            backoff: Duration::from_millis(prop.retry_backoff),
+            max_backoff: Duration::from_millis(prop.retry_max_backoff),
+            budget,
+        })
+    }
+
+    /// Starts tracking the attempts of one operation.
+    pub fn start(&self) -> Retries<'_> {
+        if let Some(budget) = &self.budget {
+            budget.deposit();
+        }
+        Retries {
+            policy: self,
+            attempts: 0,
+            failed: false,
+            succeeded: false,
+        }
+    }
+
+    fn delay(&self, attempts: u64) -> Duration {
+        match self.kind {
+            PolicyKind::Exponential => {
+                let shift = (attempts - 1).min(31) as u32;
+                let ceiling = self.backoff.saturating_mul(1 << shift).min(self.max_backoff);
+                // Full jitter, so that clients that failed together don't
+                // retry together.
+                rand::thread_rng().gen_range(Duration::ZERO..=ceiling)
+            }
+            PolicyKind::None | PolicyKind::Fixed | PolicyKind::Amplify => Duration::ZERO,
+        }
+    }
+}
+
+/// How an attempt went.
+pub enum Attempt {
+    Succeeded,
+    Failed { retryable: bool },
+}
+
+impl Attempt {
+    /// Errors are worth retrying only if their kind is transient.
+    pub fn from_result(result: &DbResult<()>) -> Self {
+        match result {
+            Ok(()) => Attempt::Succeeded,
+            Err(err) => Attempt::Failed { retryable: err.is_retryable() },
+        }
+    }
+}
+
+/// The attempts of one operation so far.
+pub struct Retries<'a> {
+    policy: &'a RetryPolicy,
+    attempts: u64,
+    failed: bool,
+    succeeded: bool,
+}
+
+impl Retries<'_> {
+    /// Whether the next attempt is a retry.
+    pub fn is_retry(&self) -> bool {
+        self.attempts > 0
+    }
+
+    /// Whether any attempt succeeded.
+    pub fn succeeded(&self) -> bool {
+        self.succeeded
+    }
+
+    /// Records an attempt and returns how long to wait before the next one,
+    /// or `None` if the operation is done.
+    pub fn next(&mut self, attempt: Attempt) -> Option<Duration> {
+        self.attempts += 1;
+        let policy = self.policy;
+        match attempt {
+            Attempt::Succeeded => {
+                self.succeeded = true;
+                let amplify = policy.kind == PolicyKind::Amplify && self.failed;
+                (amplify && self.attempts < policy.attempts).then_some(Duration::ZERO)
+            }
+            Attempt::Failed { retryable } => {
+                self.failed = true;
+                if !retryable || self.attempts >= policy.attempts {
+                    return None;
+                }
+                if let Some(budget) = &policy.budget {
+                    if !budget.withdraw() {
+                        return None;
+                    }
+                }
+                Some(policy.delay(self.attempts))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(kind: &str, budget: Option<f64>) -> RetryPolicy {
+        let mut prop = crate::testing::props("operationcount = 1");
+        prop.retry_policy = kind.to_string();
+        prop.retry_budget = budget;
+        RetryPolicy::new(&prop, 3).unwrap()
+    }
+
+    const RETRYABLE: Attempt = Attempt::Failed { retryable: true };
+
+    #[test]
+    fn test_attempt_limits() {
+        let none = policy("none", None);
+        assert_eq!(none.start().next(RETRYABLE), None);
+
+        let fixed = policy("fixed", None);
+        let mut retries = fixed.start();
+        assert_eq!(retries.next(RETRYABLE), Some(Duration::ZERO));
+        assert_eq!(retries.next(RETRYABLE), Some(Duration::ZERO));
+        assert_eq!(retries.next(RETRYABLE), None);
+        assert!(!retries.succeeded());
+        assert_eq!(fixed.start().next(Attempt::Failed { retryable: false }), None);
+
+        let amplify = policy("amplify", None);
+        let mut retries = amplify.start();
+        assert!(retries.next(RETRYABLE).is_some());
+        assert!(retries.next(Attempt::Succeeded).is_some());
+        assert_eq!(retries.next(Attempt::Succeeded), None);
+        assert!(retries.succeeded());
+    }
+
+    #[test]
+    fn test_exponential_backoff_is_capped() {
+        let exponential = policy("exponential", None);
+        for attempts in 1..40 {
+            assert!(exponential.delay(attempts) <= exponential.max_backoff);
+        }
+    }
+
+    #[test]
+    fn test_budget() {
+        let budgeted = policy("fixed", Some(0.0));
+        let granted = (0..20).filter(|_| budgeted.start().next(RETRYABLE).is_some()).count();
+        assert_eq!(granted as u64, BUDGET_RESERVE / BUDGET_UNIT);
+    }
+}
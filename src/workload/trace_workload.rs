@@ -0,0 +1,304 @@
+use super::core_workload::fnv1a;
+use super::{CoreWorkload, Workload};
+use crate::db::DBType;
+use crate::properties::Properties;
+use crate::trace::TracedOperation;
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use rand::rngs::SmallRng;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum LogOp {
+    Read,
+    Update,
+    Insert,
+    Scan,
+}
+
+impl std::str::FromStr for LogOp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "read" => Ok(LogOp::Read),
+            "update" => Ok(LogOp::Update),
+            "insert" => Ok(LogOp::Insert),
+            "scan" => Ok(LogOp::Scan),
+            _ => Err(anyhow!("unknown operation {}", s)),
+        }
+    }
+}
+
+/// One access of a key access log. `size` is the bytes written by updates
+/// and inserts, or the records read by scans.
+#[derive(Deserialize, Debug, PartialEq)]
+struct LogRecord {
+    op: LogOp,
+    key: String,
+    #[serde(default)]
+    size: u64,
+    /// When the access happened, in ms since any fixed point.
+    time_ms: Option<f64>,
+}
+
+/// `op,key,size[,time_ms]`, with an optional header line.
+fn parse_csv(line: &str) -> Result<Option<LogRecord>> {
+    let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+    if columns[0] == "op" {
+        return Ok(None);
+    }
+    if columns.len() < 3 {
+        bail!("expected op,key,size[,time_ms]");
+    }
+    Ok(Some(LogRecord {
+        op: columns[0].parse()?,
+        key: columns[1].to_string(),
+        size: columns[2].parse()?,
+        time_ms: columns.get(3).map(|time| time.parse()).transpose()?,
+    }))
+}
+
+fn read_log(path: &str) -> Result<Vec<LogRecord>> {
+    let csv = path.ends_with(".csv");
+    let file = BufReader::new(File::open(path)?);
+    let mut records = vec![];
+    for (idx, line) in file.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = if csv {
+            parse_csv(&line)
+        } else {
+            serde_json::from_str(&line).map(Some).map_err(anyhow::Error::from)
+        };
+        match record {
+            Ok(Some(record)) => records.push(record),
+            Ok(None) => {}
+            Err(err) => bail!("{}:{}: {}", path, idx + 1, err),
+        }
+    }
+    Ok(records)
+}
+
+/// Issues the accesses of a key access log during `run`, optionally on the
+/// log's own schedule. Loads and runs everything through `core`.
+pub struct TraceWorkload {
+    core: Arc<CoreWorkload>,
+    records: Vec<LogRecord>,
+    /// Index of the next access to issue, counting every loop.
+    next: AtomicUsize,
+    looping: bool,
+    rate: f64,
+    remap_keys: bool,
+    /// Time of the first access, and from it to the last one.
+    first_ms: f64,
+    span_ms: f64,
+    start: Instant,
+}
+
+impl TraceWorkload {
+    pub fn new(prop: &Properties, core: Arc<CoreWorkload>) -> Result<Self> {
+        let path = prop
+            .trace_file
+            .as_ref()
+            .ok_or_else(|| anyhow!("the trace workload needs tracefile"))?;
+        if prop.trace_rate < 0.0 {
+            bail!("tracerate must not be negative");
+        }
+        let records = read_log(path)?;
+        let times = records.iter().filter_map(|record| record.time_ms);
+        let first_ms = times.clone().fold(f64::INFINITY, f64::min);
+        let last_ms = times.fold(f64::NEG_INFINITY, f64::max);
+        Ok(TraceWorkload {
+            core,
+            records,
+            next: AtomicUsize::new(0),
+            looping: prop.trace_loop,
+            rate: prop.trace_rate,
+            remap_keys: prop.trace_remap_keys,
+            first_ms,
+            span_ms: (last_ms - first_ms).max(0.0),
+            start: Instant::now(),
+        })
+    }
+
+    /// Takes the next access, along with how many times the log has been
+    /// gone through before it.
+    fn next_record(&self) -> Option<(usize, &LogRecord)> {
+        let idx = self.next.fetch_add(1, Ordering::Relaxed);
+        let lap = idx / self.records.len().max(1);
+        if lap > 0 && !self.looping {
+            return None;
+        }
+        self.records.get(idx % self.records.len().max(1)).map(|record| (lap, record))
+    }
+
+    /// When `record` is due in ms since the phase started, scaled by
+    /// `tracerate`, or `None` if it isn't scheduled.
+    fn due_ms(&self, lap: usize, record: &LogRecord) -> Option<f64> {
+        match record.time_ms {
+            Some(time_ms) if self.rate > 0.0 => Some((lap as f64 * self.span_ms + time_ms - self.first_ms) / self.rate),
+            _ => None,
+        }
+    }
+
+    /// Waits until `record` is due.
+    async fn wait_for(&self, lap: usize, record: &LogRecord) {
+        if let Some(offset_ms) = self.due_ms(lap, record) {
+            tokio::time::sleep_until(self.start + Duration::from_secs_f64(offset_ms / 1000.0)).await;
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        if self.remap_keys {
+            let record_count = self.core.record_count().max(1) as u64;
+            // Spreads arbitrary keys over the loaded records.
+            self.core.build_key_name(fnv1a(key.bytes()) % record_count)
+        } else {
+            key.to_string()
+        }
+    }
+
+    /// Spreads `size` bytes evenly over the workload's fields.
+    fn sizes(&self, size: u64) -> BTreeMap<String, usize> {
+        let fields = self.core.field_names();
+        let count = fields.len() as u64;
+        fields
+            .iter()
+            .enumerate()
+            .map(|(idx, field)| (field.clone(), (size / count + u64::from((idx as u64) < size % count)) as usize))
+            .collect()
+    }
+
+    fn operation(&self, record: &LogRecord) -> TracedOperation {
+        let key = self.key(&record.key);
+        match record.op {
+            LogOp::Read => TracedOperation::Read { key },
+            LogOp::Update => TracedOperation::Update { key, sizes: self.sizes(record.size) },
+            LogOp::Insert => TracedOperation::Insert { key, sizes: self.sizes(record.size) },
+            LogOp::Scan => TracedOperation::Scan { key, count: record.size.max(1) as usize },
+        }
+    }
+}
+
+#[async_trait]
+impl Workload for TraceWorkload {
+    async fn do_insert(&self, db: DBType, rng: &mut SmallRng, keynum: u64) {
+        self.core.do_insert(db, rng, keynum).await;
+    }
+
+    async fn do_insert_batch(&self, db: DBType, rng: &mut SmallRng, keynum: u64, count: usize) {
+        self.core.do_insert_batch(db, rng, keynum, count).await;
+    }
+
+    async fn do_update(&self, db: DBType, rng: &mut SmallRng) {
+        self.core.do_update(db, rng).await;
+    }
+
+    async fn do_transaction(&self, db: DBType, rng: &mut SmallRng) {
+        if let Some((lap, record)) = self.next_record() {
+            self.wait_for(lap, record).await;
+            self.core.replay(db, rng, &self.operation(record)).await;
+        }
+    }
+
+    async fn do_transactions_in_txn(&self, db: DBType, rng: &mut SmallRng, count: usize) {
+        let records: Vec<_> = (0..count).filter_map(|_| self.next_record()).collect();
+        if let Some((lap, record)) = records.first() {
+            self.wait_for(*lap, record).await;
+            let ops = records.iter().map(|(_, record)| self.operation(record)).collect();
+            self.core.replay(db, rng, &TracedOperation::Transaction { ops }).await;
+        }
+    }
+
+    fn run_count(&self, operation_count: usize) -> usize {
+        if self.looping {
+            operation_count
+        } else {
+            operation_count.min(self.records.len())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// A trace workload over `log`, with `prop` added to its properties.
+    fn workload(name: &str, log: &str, prop: &str) -> TraceWorkload {
+        let path = std::env::temp_dir().join(format!("ycsb-{}-{}.csv", name, std::process::id()));
+        std::fs::write(&path, log).unwrap();
+        let prop = format!("operationcount = 1\nrecordcount = 100\ntracefile = {:?}\n{}", path.to_str().unwrap(), prop);
+        let prop = testing::props(&prop);
+        let core = Arc::new(testing::core_workload(&prop).unwrap());
+        let wl = TraceWorkload::new(&prop, core).unwrap();
+        std::fs::remove_file(path).unwrap();
+        wl
+    }
+
+    const LOG: &str = "op,key,size,time_ms\nread,a,0,100\nupdate,b,10,200\nread,c,0,300\n";
+
+    #[test]
+    fn test_next_record_and_run_count() {
+        let once = workload("once", LOG, "");
+        let keys: Vec<_> = std::iter::from_fn(|| once.next_record()).map(|(lap, record)| (lap, &record.key[..])).collect();
+        assert_eq!(keys, [(0, "a"), (0, "b"), (0, "c")]);
+        assert_eq!(once.run_count(10), 3);
+        assert_eq!(once.run_count(2), 2);
+
+        let looping = workload("looping", LOG, "traceloop = true");
+        let keys: Vec<_> = (0..5).map(|_| looping.next_record().map(|(lap, record)| (lap, &record.key[..]))).collect();
+        assert_eq!(keys, [Some((0, "a")), Some((0, "b")), Some((0, "c")), Some((1, "a")), Some((1, "b"))]);
+        assert_eq!(looping.run_count(10), 10);
+    }
+
+    #[test]
+    fn test_trace_rate_schedule() {
+        let wl = workload("rate", LOG, "tracerate = 2.0");
+        let due: Vec<_> = wl.records.iter().map(|record| wl.due_ms(0, record)).collect();
+        assert_eq!(due, [Some(0.0), Some(50.0), Some(100.0)]);
+        // The next lap starts where this one ends.
+        assert_eq!(wl.due_ms(1, &wl.records[1]), Some(150.0));
+
+        let unscheduled = workload("unscheduled", LOG, "tracerate = 0.0");
+        assert_eq!(unscheduled.due_ms(0, &unscheduled.records[1]), None);
+    }
+
+    #[test]
+    fn test_remap_keys() {
+        let wl = workload("remap", LOG, "traceremapkeys = true");
+        let loaded: Vec<_> = (0..100).map(|keynum| wl.core.build_key_name(keynum)).collect();
+        let key = wl.key("user42");
+        assert!(loaded.contains(&key));
+        assert_eq!(wl.key("user42"), key);
+        assert_eq!(workload("verbatim", LOG, "").key("user42"), "user42");
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        assert_eq!(parse_csv("op,key,size").unwrap(), None);
+        assert_eq!(
+            parse_csv("update, user42, 100, 12.5").unwrap(),
+            Some(LogRecord {
+                op: LogOp::Update,
+                key: "user42".to_string(),
+                size: 100,
+                time_ms: Some(12.5),
+            })
+        );
+        assert!(parse_csv("delete,user42,0").is_err());
+        let record: LogRecord = serde_json::from_str(r#"{"op":"read","key":"user42"}"#).unwrap();
+        assert_eq!(record.op, LogOp::Read);
+        assert_eq!(record.time_ms, None);
+    }
+}